@@ -0,0 +1,587 @@
+use std::{
+    borrow::Cow,
+    env, fs, io,
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt},
+        process::CommandExt,
+    },
+    path::PathBuf,
+    process::{ExitStatus, Output, Stdio},
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::{bail, Result};
+use crossterm::terminal;
+use futures::future::OptionFuture;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::{self, ChildStderr, ChildStdout},
+    select,
+    sync::{mpsc::Receiver, watch},
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    config::{Config, Container},
+    graphics::{Image, ImageFormat},
+    output::OutputBuffer,
+    shell::{self, TokenKind},
+};
+
+#[derive(Debug)]
+pub enum Cmd {
+    /// Runs a command, with what to feed it on stdin if anything.
+    Input(String, Option<Arc<str>>),
+    Done,
+}
+
+/// How to run commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exec {
+    Shell,
+    /// Split into words like the shell would, but run without one, so
+    /// nothing is expanded.
+    Direct,
+}
+
+/// The output of the current command, sent whenever it changes.
+///
+/// Each update replaces the previous one, so the event loop only ever needs
+/// to look at the latest.
+#[derive(Debug, Default)]
+pub struct Update {
+    /// The command the output is from.
+    pub command: String,
+    pub output: String,
+    /// Everything the command printed on stdout, once it is done, unless
+    /// that isn't UTF-8 or was truncated. This is what gets piped on.
+    pub stdout: Option<String>,
+    /// How the command exited, once it has.
+    pub status: Option<ExitStatus>,
+    /// The image the command printed, once it is complete.
+    pub image: Option<Image>,
+    /// Whether the command has exited, so no more updates will follow.
+    pub done: bool,
+}
+
+pub async fn child_handler(
+    mut cmd_chan: Receiver<Cmd>,
+    output_chan: watch::Sender<Update>,
+    config: Arc<Config>,
+    exec: Exec,
+) -> Result<()> {
+    let mut run: Option<Run> = None;
+    // Output that arrived too soon after the last update, which is sent once
+    // the minimum interval is over unless more output replaces it first.
+    let mut pending: Option<Update> = None;
+    let mut next_update = Instant::now();
+
+    loop {
+        select! {
+            Some(update) = OptionFuture::from(run.as_mut().map(Run::next)) => {
+                if update.done {
+                    run = None;
+                }
+                let now = Instant::now();
+                if update.done || now >= next_update {
+                    pending = None;
+                    next_update = now + config.min_update_interval;
+                    output_chan.send(update)?;
+                } else {
+                    pending = Some(update);
+                }
+            },
+            Some(()) = OptionFuture::from(pending.is_some().then(|| sleep_until(next_update))) => {
+                if let Some(update) = pending.take() {
+                    next_update = Instant::now() + config.min_update_interval;
+                    output_chan.send(update)?;
+                }
+            },
+            msg = cmd_chan.recv() => {
+                // Dropping the previous run kills its processes.
+                run = None;
+                pending = None;
+                next_update = Instant::now();
+                match msg {
+                    Some(Cmd::Input(input, stdin)) => match Run::spawn(&input, stdin, &config, exec) {
+                        Ok(r) => run = Some(r),
+                        Err(e) => {
+                            output_chan.send(Update {
+                                command: input,
+                                output: format!("Failed to run command: {e}"),
+                                stdout: None,
+                                status: None,
+                                image: None,
+                                done: true,
+                            })?;
+                        }
+                    },
+                    Some(Cmd::Done) | None => return Ok(()),
+                }
+            },
+        }
+    }
+}
+
+/// A running command and the output it produced so far.
+struct Run {
+    input: String,
+    child: process::Child,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    stdout_buf: OutputBuffer,
+    stderr_buf: OutputBuffer,
+    /// Stdout as is, if it starts like an image.
+    image: Option<(ImageFormat, Vec<u8>)>,
+    /// Whether anything was read from stdout yet.
+    stdout_started: bool,
+    /// The host the command runs on with `--ssh`.
+    ssh_host: Option<String>,
+    /// The process group of the command, which the shell leads.
+    group: Option<libc::pid_t>,
+}
+
+impl Run {
+    fn spawn(
+        input: &str,
+        stdin: Option<Arc<str>>,
+        config: &Config,
+        exec: Exec,
+    ) -> io::Result<Self> {
+        let mut command = match exec {
+            Exec::Shell => command(&with_prefix(input, config), config)?,
+            Exec::Direct => direct_command(input, config)?,
+        };
+        // Without anything to feed them, give commands EOF right away,
+        // otherwise filters like `cat` or `sort` wait for input forever.
+        let mut child = command
+            .stdin(match stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let (Some(mut pipe), Some(stdin)) = (child.stdin.take(), stdin) {
+            // Written on the side, as the command may only read as much as
+            // it prints and the output has to be read meanwhile. If it exits
+            // or is killed first, this fails and the rest is dropped.
+            tokio::spawn(async move {
+                let _ = pipe.write_all(stdin.as_bytes()).await;
+            });
+        }
+        Ok(Self {
+            input: input.to_string(),
+            group: child.id().and_then(|id| libc::pid_t::try_from(id).ok()),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            child,
+            stdout_buf: OutputBuffer::new(config.output_limit.clone(), config.null_delimited),
+            // Error messages are lines either way.
+            stderr_buf: OutputBuffer::new(config.output_limit.clone(), false),
+            image: None,
+            stdout_started: false,
+            ssh_host: config.ssh.clone(),
+        })
+    }
+
+    /// Waits for the command to print something or exit.
+    async fn next(&mut self) -> Update {
+        let mut stdout_chunk = [0; 4096];
+        let mut stderr_chunk = [0; 4096];
+
+        loop {
+            select! {
+                Some(n) = read(&mut self.stdout, &mut stdout_chunk), if self.stdout.is_some() => {
+                    let chunk = &stdout_chunk[..n];
+                    if !self.stdout_started {
+                        self.stdout_started = true;
+                        self.image = ImageFormat::detect(chunk).map(|format| (format, vec![]));
+                    }
+                    match &mut self.image {
+                        Some((_, data)) => data.extend_from_slice(chunk),
+                        None => self.stdout_buf.push(chunk),
+                    }
+                    return self.update(false);
+                },
+                Some(n) = read(&mut self.stderr, &mut stderr_chunk), if self.stderr.is_some() => {
+                    self.stderr_buf.push(&stderr_chunk[..n]);
+                    return self.update(false);
+                },
+                // Only wait once both streams are closed, so that no output
+                // gets lost.
+                status = self.child.wait(), if self.stdout.is_none() && self.stderr.is_none() => {
+                    self.stdout_buf.finish();
+                    self.stderr_buf.finish();
+                    let mut update = Update {
+                        status: status.ok(),
+                        ..self.update(true)
+                    };
+                    // SSH exits with 255 if it couldn't connect, and explains
+                    // why on stderr.
+                    if let Some(host) = &self.ssh_host {
+                        if update.status.and_then(|s| s.code()) == Some(255) {
+                            update.output = format!("Failed to connect to {host}: {}", update.output.trim());
+                        }
+                    }
+                    return update;
+                },
+                // A stream just closed, check again whether to wait for exit.
+                else => {},
+            }
+        }
+    }
+
+    fn update(&self, done: bool) -> Update {
+        if let Some((format, data)) = &self.image {
+            return Update {
+                command: self.input.clone(),
+                output: format!("Binary image ({format}), {} bytes", data.len()),
+                stdout: None,
+                status: None,
+                image: done.then(|| Image {
+                    format: *format,
+                    data: data.as_slice().into(),
+                }),
+                done,
+            };
+        }
+
+        // Stderr is only interesting if there is nothing on stdout.
+        let output = if !self.stdout_buf.is_empty() {
+            self.stdout_buf
+                .text()
+                .unwrap_or_else(|| "Non-UTF8 stdout".to_string())
+        } else {
+            self.stderr_buf
+                .text()
+                .unwrap_or_else(|| "Non-UTF8 stderr".to_string())
+        };
+        let stdout = done
+            .then(|| self.stdout_buf.text())
+            .flatten()
+            .filter(|_| !self.stdout_buf.is_truncated());
+        Update {
+            command: self.input.clone(),
+            output,
+            stdout,
+            status: None,
+            image: None,
+            done,
+        }
+    }
+}
+
+impl Drop for Run {
+    /// Kills the whole process group, as killing the shell alone would leave
+    /// the rest of a pipeline or anything started in the background running.
+    /// The group can outlive the shell, so this happens even once it exited.
+    fn drop(&mut self) {
+        if let Some(group) = self.group {
+            // SAFETY: kill doesn't touch memory. The group ID is the PID of
+            // the shell, which can't be reused while any process in the
+            // group is left or the shell isn't reaped. Once the group is
+            // empty it can be, but the child handler drops a run right
+            // after the shell is reaped, leaving next to no time for that.
+            unsafe {
+                libc::kill(-group, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// The command as the shell gets it, after `--prefix`. Blank input is left
+/// alone, as a prefix like `cd /tmp &&` on its own is a syntax error.
+pub fn with_prefix<'a>(input: &'a str, config: &Config) -> Cow<'a, str> {
+    match &config.prefix {
+        Some(prefix) if !input.trim().is_empty() => Cow::Owned(format!("{prefix} {input}")),
+        _ => Cow::Borrowed(input),
+    }
+}
+
+/// Runs a command to completion, collecting its output.
+pub async fn output(input: &str, config: &Config) -> io::Result<Output> {
+    command(input, config)?.stdin(Stdio::null()).output().await
+}
+
+/// Runs a formatter on a command, which it gets on stdin.
+pub async fn format(formatter: &str, input: &str, config: &Config) -> io::Result<Output> {
+    let mut child = local_command(formatter, config)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // A formatter that exits without reading, like when it isn't installed,
+    // explains itself on stderr.
+    match stdin.write_all(input.as_bytes()).await {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+        _ => {}
+    }
+    // Close stdin so the formatter knows the command is complete.
+    drop(stdin);
+    child.wait_with_output().await
+}
+
+/// Runs a hook, passing it the current command in the environment and the
+/// output on stdin.
+///
+/// The output can be too long for an environment variable, which Linux
+/// limits to 128 KiB.
+pub async fn hook(hook: &str, command: &str, output: &str, config: &Config) -> io::Result<Output> {
+    let mut child = local_command(hook, config)
+        .env("LIVE_PREVIEW_COMMAND", command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let output = output.to_string();
+    // Written on the side so that a hook printing a lot before it reads
+    // doesn't block. Hooks that don't read it at all close the pipe, which
+    // is fine.
+    let writer = tokio::spawn(async move {
+        match stdin.write_all(output.as_bytes()).await {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            _ => Ok(()),
+        }
+    });
+    let result = child.wait_with_output().await?;
+    writer.await.map_err(io::Error::other)??;
+    Ok(result)
+}
+
+/// Checks that a container exists and is running, so that this doesn't
+/// turn up as an error for every command.
+pub fn check_container(container: &Container) -> Result<()> {
+    let output = std::process::Command::new(container.engine)
+        .args(["inspect", "--format", "{{.State.Running}}", &container.name])
+        .stdin(Stdio::null())
+        .output()?;
+    let Container { engine, name } = container;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("no {engine} container {name}: {}", stderr.trim());
+    }
+    if String::from_utf8_lossy(&output.stdout).trim() != "true" {
+        bail!("{engine} container {name} isn't running");
+    }
+    Ok(())
+}
+
+/// Builds the process to run `input` in, which is on the remote host with
+/// `--ssh` or in the container with `--docker` or `--podman`.
+fn command(input: &str, config: &Config) -> io::Result<process::Command> {
+    if let Some(container) = &config.container {
+        let mut command = container_exec(container, config);
+        command.arg("sh").arg("-c").arg(input);
+        return Ok(command);
+    }
+    let Some(host) = &config.ssh else {
+        return Ok(local_command(input, config));
+    };
+    // Share one connection between all runs rather than connecting on every
+    // key press, and keep it open for a bit in case of another run.
+    let control_path = private_dir()?.join("ssh-%C");
+    let mut command = process(config, "ssh");
+    for option in [
+        "ControlMaster=auto".to_string(),
+        format!("ControlPath={}", control_path.display()),
+        "ControlPersist=60".to_string(),
+        // There is no way to enter a password, so fail instead of asking.
+        "BatchMode=yes".to_string(),
+        "ConnectTimeout=10".to_string(),
+    ] {
+        command.arg("-o").arg(option);
+    }
+    // The remote login shell could be anything, so run the command in sh.
+    let remote = format!(
+        "env COLUMNS={} sh -c {}",
+        columns(config),
+        shell::quote(input)
+    );
+    command.arg(host).arg("--").arg(remote);
+    Ok(command)
+}
+
+/// A directory only the user can get at, for the SSH control socket, since
+/// anyone who can connect to it can run commands on the remote host.
+///
+/// This is in `$XDG_RUNTIME_DIR` if there is one, and otherwise in the
+/// temporary directory, where it is checked that nobody else made it first.
+fn private_dir() -> io::Result<PathBuf> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) => PathBuf::from(runtime).join("live-preview"),
+        // SAFETY: getuid always succeeds and doesn't touch memory.
+        None => env::temp_dir().join(format!("live-preview-{}", unsafe { libc::getuid() })),
+    };
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("can't create {}: {e}", dir.display()),
+            ));
+        }
+        _ => {}
+    }
+    let metadata = fs::symlink_metadata(&dir)?;
+    // SAFETY: as above.
+    let owned = metadata.uid() == unsafe { libc::getuid() };
+    if !metadata.is_dir() || !owned || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is accessible to other users", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+/// Builds the process to run `input` in on this machine.
+fn local_command(input: &str, config: &Config) -> process::Command {
+    let mut command = process(config, "zsh");
+    command.arg("-c").arg(input);
+    command
+}
+
+/// Builds the process to run `input` without a shell.
+fn direct_command(input: &str, config: &Config) -> io::Result<process::Command> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if config.ssh.is_some() {
+        return Err(invalid("SSH always runs commands in a shell".to_string()));
+    }
+    let tokens = shell::tokenize(input).map_err(|e| invalid(e.to_string()))?;
+    if let Some(token) = tokens.iter().find(|t| t.kind != TokenKind::Word) {
+        return Err(invalid(format!("{} needs a shell", token.value)));
+    }
+    let Some((program, args)) = tokens.split_first() else {
+        return Err(invalid("no command".to_string()));
+    };
+    let mut command = match &config.container {
+        Some(container) => {
+            let mut command = container_exec(container, config);
+            command.arg(&program.value);
+            command
+        }
+        None => process(config, &program.value),
+    };
+    command.args(args.iter().map(|t| &t.value));
+    Ok(command)
+}
+
+/// Builds the process to run something in a container with, up to the
+/// program to run.
+fn container_exec(container: &Container, config: &Config) -> process::Command {
+    let mut command = process(config, container.engine);
+    command
+        .arg("exec")
+        // Forward stdin, for output piped into the command.
+        .arg("--interactive")
+        .arg("--env")
+        .arg(format!("COLUMNS={}", columns(config)))
+        .arg(&container.name);
+    command
+}
+
+/// Builds a process in a process group of its own, so that it and
+/// everything it starts can be killed together.
+fn process(config: &Config, program: &str) -> process::Command {
+    let mut command = std::process::Command::new(program);
+    // Tokio only has this behind `tokio_unstable`, so set it up with std.
+    command
+        .env("COLUMNS", columns(config).to_string())
+        .process_group(0);
+    let mut command = process::Command::from(command);
+    command.kill_on_drop(true);
+    command
+}
+
+/// Reads the next chunk from `stream`, closing it on EOF.
+async fn read(stream: &mut Option<impl AsyncRead + Unpin>, chunk: &mut [u8]) -> Option<usize> {
+    let s = stream.as_mut()?;
+    match s.read(chunk).await {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            *stream = None;
+            None
+        }
+    }
+}
+
+/// Width to run commands at, which is the width of the output pane as last
+/// drawn unless overridden.
+fn columns(config: &Config) -> u16 {
+    config.columns.unwrap_or_else(|| {
+        let width = config.pane_width.load(Ordering::Relaxed);
+        if width > 0 {
+            return width;
+        }
+        // Before the first draw, guess from the size of the terminal.
+        // Subtract the margin and the borders on either side, which the
+        // compact layout doesn't have.
+        let chrome = if config.compact { 0 } else { 4 };
+        terminal::size().map_or(80, |(width, _)| width.saturating_sub(chrome))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn hook_gets_long_output_on_stdin() {
+        // Longer than an environment variable can be.
+        let output = "x".repeat(256 * 1024);
+        let result = hook(
+            "wc -c; echo $LIVE_PREVIEW_COMMAND",
+            "cmd",
+            &output,
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        let stdout = String::from_utf8(result.stdout).unwrap();
+        let lines: Vec<_> = stdout.split_whitespace().collect();
+        assert_eq!(lines, [output.len().to_string().as_str(), "cmd"]);
+    }
+
+    #[test]
+    fn private_dir_is_private() {
+        let dir = private_dir().unwrap();
+        let metadata = fs::metadata(&dir).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn prefix_is_left_off_blank_input() {
+        let config = Config {
+            prefix: Some("cd /tmp &&".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(with_prefix("ls", &config), "cd /tmp && ls");
+        assert_eq!(with_prefix("  ", &config), "  ");
+    }
+
+    #[tokio::test]
+    async fn dropping_a_run_kills_the_pipeline() {
+        let config = Config::default();
+        let run = Run::spawn("sleep 100 | sleep 100", None, &config, Exec::Shell).unwrap();
+        let group = run.group.unwrap();
+        // Give the shell time to start both commands.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        drop(run);
+
+        // The killed processes take a moment to be reaped.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            // SAFETY: signal 0 only checks whether the group exists.
+            let result = unsafe { libc::kill(-group, 0) };
+            if result == -1 && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "the pipeline is still running");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// A way to complete the word before the cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The whole word once completed.
+    pub word: String,
+    /// How to show it in the picker, with `/` after directories and `*`
+    /// after executables like `ls -F`.
+    pub label: String,
+}
+
+/// Finds the completions of `word`, which are commands on the `PATH` if
+/// `is_command` is set and the word isn't a path, or file paths otherwise.
+pub fn candidates(word: &str, is_command: bool) -> Vec<Candidate> {
+    if is_command && !word.contains('/') {
+        commands(word)
+    } else {
+        paths(word)
+    }
+}
+
+/// Whether the word starting at the end of `before` is in command position,
+/// as in `gr` or `ls | gr`.
+pub fn is_command(before: &str) -> bool {
+    let before = before.trim_end();
+    before.is_empty() || before.ends_with(['|', '&', ';', '(', '\n'])
+}
+
+fn commands(prefix: &str) -> Vec<Candidate> {
+    let Some(path) = env::var_os("PATH") else {
+        return vec![];
+    };
+    // Commands earlier on the path shadow later ones with the same name.
+    let mut names = BTreeSet::new();
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                names.insert(name);
+            }
+        }
+    }
+    names
+        .into_iter()
+        .map(|name| Candidate {
+            label: name.clone(),
+            word: name,
+        })
+        .collect()
+}
+
+fn paths(word: &str) -> Vec<Candidate> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let read_dir = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        PathBuf::from(dir)
+    };
+    let Ok(entries) = fs::read_dir(read_dir) else {
+        return vec![];
+    };
+
+    let mut candidates: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Hidden files only show up when asked for.
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // Follow symlinks so links to directories complete like them.
+            let path = entry.path();
+            let (word, label) = if path.is_dir() {
+                (format!("{dir}{name}/"), format!("{name}/"))
+            } else if is_executable(&path) {
+                (format!("{dir}{name}"), format!("{name}*"))
+            } else {
+                (format!("{dir}{name}"), name)
+            };
+            Some(Candidate { word, label })
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.word.cmp(&b.word));
+    candidates
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
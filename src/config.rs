@@ -0,0 +1,570 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::AtomicU16, Arc},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossterm::cursor::CursorShape;
+use regex::Regex;
+use tui::style::Color;
+
+use crate::{
+    dry_run,
+    graphics::Protocol,
+    history::Dedup,
+    output::{Limit, Truncate},
+    playlist::Playlist,
+    references, safety,
+    samples::{self, Sample},
+    template::Template,
+    timestamps::TimestampFormat,
+};
+
+const USAGE: &str = "\
+Usage: live-preview [OPTIONS]
+
+Options:
+      --columns <N>             Width reported to commands via $COLUMNS
+                                [default: width of the output pane]
+      --output-format <FORMAT>  How to print the output on accept
+                                [default: raw] [possible values: raw, markdown]
+      --timestamp-format <FORMAT>
+                                How F2 shows timestamps in the output
+                                [default: relative] [possible values: relative,
+                                local]
+      --graphics <PROTOCOL>     Show images commands print with a terminal
+                                graphics protocol [default: none]
+                                [possible values: auto, kitty, iterm2, none]
+      --empty-enter <ACTION>    What Enter does while the command is empty
+                                [default: ignore]
+                                [possible values: ignore, abort, accept]
+      --print-on-exit           Leave the final command and its output in the
+                                terminal's scrollback
+      --compact                 Leave out the borders and show the command in
+                                a prompt line above the output
+      --no-focus-events         Keep redrawing while the terminal is in the
+                                background, for terminals that don't report
+                                focus changes reliably
+      --cursor-style <STYLE>    The shape of the cursor in the input
+                                [default: the terminal's]
+                                [possible values: block, bar, underline]
+      --cursor-color <COLOR>    The colour of the cursor, a name like red or
+                                #rrggbb, if the terminal supports it
+      --indicator-delay <MS>    How long a command runs before it is shown as
+                                running [default: 100]
+      --highlight-changes <MS>  Highlight the output lines that changed since
+                                the command was last run, for this long
+      --highlight-color <COLOR> What to highlight changed lines with, a name
+                                like yellow or #rrggbb [default: yellow]
+      --page-size <LINES>       How far Page Up and Page Down scroll
+                                [default: the height of the output pane]
+      --max-line-width <N>      Cut output lines off after this many columns,
+                                Ctrl+W shows the current one in full
+                                [default: the width of the output pane]
+      --ellipsis <TEXT>         Shown where a line was cut off [default: …]
+      --null-delimited          Show output separated by null bytes, like from
+                                find -print0, one record per line
+      --max-output-bytes <N>    Keep at most this much output [default: all]
+      --truncate <END>          Which end of the output to drop beyond
+                                --max-output-bytes [default: head]
+                                [possible values: head, tail]
+      --truncate-marker <TEXT>  Shown where output was dropped
+                                [default: \"[output truncated]\"]
+      --min-update-interval <MS>
+                                Show new output of a running command at most
+                                this often [default: 0]
+      --prefix <TEXT>           Run every command after this, like
+                                'cd ~/project &&', without showing it in the
+                                input
+      --template <TEMPLATE>     Fill in the fields of a command like
+                                'grep {pattern} {file}' instead of typing it
+      --ssh <HOST>              Run commands on a remote host over SSH, which
+                                needs to work without a password
+      --docker <CONTAINER>      Run commands in a running Docker container
+      --podman <CONTAINER>      Run commands in a running Podman container
+      --playlist <FILE>         Step through the commands in a file, one per
+                                line, with Ctrl+P and Ctrl+N
+      --sample <NAME=CMD>       Add a sample value for Ctrl+Y that is whatever
+                                the command prints, can be repeated
+      --formatter <CMD>         Show the command as formatted by a formatter
+                                like shfmt, which gets it on stdin
+      --select-template <TEMPLATE>
+                                What to load for an output line selected with
+                                Ctrl+O, like 'cat {line}', with the line quoted
+                                [default: the line as is]
+      --reference-pattern <REGEX>
+                                How to find file references for Ctrl+E, with
+                                `path` and `line` groups and an optional
+                                `column` one [default: path:line:column]
+      --safe-preview            Only run commands known to be read-only live,
+                                others need Ctrl+R
+      --dry-run                 Add flags like --dry-run to commands that have
+                                them when running them live, Ctrl+D to stop
+      --dry-run-flags <LIST>    Comma-separated COMMAND=FLAG pairs for
+                                --dry-run, like 'rsync=-n', replacing the
+                                defaults
+      --read-only-commands <LIST>
+                                Comma-separated commands that are safe to run
+                                live, replacing the defaults
+      --mutating-commands <LIST>
+                                Comma-separated commands that are never run
+                                live, replacing the defaults
+      --history-size <N>        How many accepted commands to keep in the
+                                history, 0 to keep none [default: 1000]
+      --history-dedup <MODE>    Which repeated commands to leave out of the
+                                history [default: consecutive]
+                                [possible values: none, consecutive, all]
+      --keylog <FILE>           Write every key pressed to a file, with the
+                                time since startup, for bug reports
+      --on-start <CMD>          Run a command on startup
+      --on-accept <CMD>         Run a command after accepting with Enter, with
+                                $LIVE_PREVIEW_COMMAND set and the output on
+                                stdin
+  -h, --help                    Print this help and exit
+
+Keys:
+  Enter      Print the output and exit
+  Esc        Exit without printing anything
+  Up/Down    Move through the output, by half a page with Shift and a
+             quarter with Alt
+  PgUp/PgDn  Move through the output by a page
+  Home/End   Go to the top or bottom of the output
+  Ctrl+P/N   Go back and forth through the history of accepted commands, or
+             through the playlist
+  Ctrl+O     Select an output line with Up/Down and load it with Enter
+  Ctrl+E     Move through file references like src/main.rs:12 in the output
+             with Up/Down and open them in $EDITOR with Enter
+  Ctrl+G     Go to a percentage of the output, or to a command in the
+             playlist by its number
+  Ctrl+T     Pick a file path to insert
+  Ctrl+X     Insert the output of another command
+  Ctrl+Y     Insert a sample value like a UUID or a date
+  Ctrl+L     Cycle line numbers: off, absolute, relative
+  Ctrl+W     Show the whole of the current output line, which is the top one
+             without line numbers
+  Tab        Complete a command or file path, or move to the next template
+             field, Shift+Tab to the previous
+  Ctrl+R     Run the command now
+  Ctrl+F     Replace the command with the formatted one, with --formatter,
+             or fix the quoting mistake shown in the status line
+  Ctrl+D     Turn the flags added with --dry-run off and on again
+  Ctrl+S     Show the output of the command run without a shell next to it
+  F3         Tidy up the whitespace in the command, Ctrl+Z to undo
+  F4         Pipe the output into the next command, which is typed from
+             scratch, F4 without a command to go back
+  F2         Show timestamps in the output as relative or local times
+  F1         Show the manual page of the command under the cursor
+  Ctrl+K     Hide the command on screen and keep it out of the history and
+             the key log, for commands with secrets in them
+  Ctrl+V     Insert the next key as is, like Tab or a control character
+";
+
+/// Options set on the command line.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Fixed terminal width to run commands at, instead of the pane width.
+    pub columns: Option<u16>,
+    /// The width of the output pane as last drawn, without the line number
+    /// gutter, which is shared with the child handlers. Zero until the first
+    /// draw.
+    pub pane_width: Arc<AtomicU16>,
+    /// How to print the accepted output.
+    pub output_format: OutputFormat,
+    /// How to show timestamps in the output when asked to.
+    pub timestamp_format: TimestampFormat,
+    /// How to show images, if at all.
+    pub graphics: Option<Protocol>,
+    /// What Enter does without a command.
+    pub empty_enter: EmptyEnter,
+    /// Whether to print the final command and output to stderr on exit.
+    pub print_on_exit: bool,
+    /// Whether to draw the interface without borders.
+    pub compact: bool,
+    /// Whether to stop redrawing while the terminal doesn't have focus.
+    pub focus_events: bool,
+    /// The shape of the cursor, unless it is left to the terminal.
+    pub cursor_style: Option<CursorShape>,
+    /// A colour for the cursor as the terminal understands it.
+    pub cursor_color: Option<String>,
+    /// How long to wait before showing that a command is running.
+    pub indicator_delay: Duration,
+    /// How long to highlight output lines that changed when a command is
+    /// run again.
+    pub highlight_changes: Option<Duration>,
+    pub highlight_color: Color,
+    /// How many lines to scroll by a page.
+    pub page_size: Option<usize>,
+    /// How many columns of each output line to show.
+    pub max_line_width: Option<usize>,
+    /// Shown at the end of lines that were cut off.
+    pub ellipsis: String,
+    /// Whether output records end in null bytes rather than newlines.
+    pub null_delimited: bool,
+    /// How much output to keep, and which.
+    pub output_limit: Option<Limit>,
+    /// How long to wait between showing new output of a running command.
+    pub min_update_interval: Duration,
+    /// Shell code to put in front of every command run live.
+    pub prefix: Option<String>,
+    /// A command with fields to fill in.
+    pub template: Option<Template>,
+    /// A host to run commands on, like `user@host`.
+    pub ssh: Option<String>,
+    /// A container to run commands in.
+    pub container: Option<Container>,
+    /// Commands to step through instead of the history.
+    pub playlist: Option<Playlist>,
+    /// The command to load for a selected output line, with `{line}` in it.
+    pub select_template: Option<String>,
+    /// How to find references to files in the output.
+    pub reference_pattern: Regex,
+    /// Kinds of values to insert for trying commands out.
+    pub samples: Vec<Sample>,
+    /// A command to format commands with.
+    pub formatter: Option<String>,
+    /// Whether to add dry-run flags to commands run live.
+    pub dry_run: bool,
+    /// Commands and their dry-run flags.
+    pub dry_run_flags: Vec<(String, String)>,
+    /// Whether to only run read-only commands live.
+    pub safe_preview: bool,
+    pub read_only_commands: Vec<String>,
+    pub mutating_commands: Vec<String>,
+    /// How many commands to keep in the history.
+    pub history_size: usize,
+    pub history_dedup: Dedup,
+    /// A file to write key presses to.
+    pub keylog: Option<PathBuf>,
+    /// A command to run on startup.
+    pub on_start: Option<String>,
+    /// A command to run with the accepted command and output.
+    pub on_accept: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            pane_width: Arc::default(),
+            output_format: OutputFormat::default(),
+            timestamp_format: TimestampFormat::default(),
+            graphics: None,
+            empty_enter: EmptyEnter::default(),
+            print_on_exit: false,
+            compact: false,
+            focus_events: true,
+            cursor_style: None,
+            cursor_color: None,
+            indicator_delay: Duration::from_millis(100),
+            highlight_changes: None,
+            highlight_color: Color::Yellow,
+            page_size: None,
+            max_line_width: None,
+            ellipsis: "…".to_string(),
+            null_delimited: false,
+            output_limit: None,
+            min_update_interval: Duration::ZERO,
+            prefix: None,
+            template: None,
+            ssh: None,
+            container: None,
+            playlist: None,
+            select_template: None,
+            reference_pattern: Regex::new(references::DEFAULT_PATTERN)
+                .expect("the default pattern is valid"),
+            samples: samples::BUILT_IN.to_vec(),
+            formatter: None,
+            dry_run: false,
+            dry_run_flags: dry_run::DRY_RUN_FLAGS
+                .iter()
+                .map(|(command, flag)| (command.to_string(), flag.to_string()))
+                .collect(),
+            safe_preview: false,
+            read_only_commands: safety::READ_ONLY_COMMANDS
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            mutating_commands: safety::MUTATING_COMMANDS
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            history_size: 1000,
+            history_dedup: Dedup::default(),
+            keylog: None,
+            on_start: None,
+            on_accept: None,
+        }
+    }
+}
+
+/// A running container to run commands in.
+#[derive(Debug, Clone)]
+pub struct Container {
+    /// The program to run it with, `docker` or `podman`.
+    pub engine: &'static str,
+    pub name: String,
+}
+
+/// Formats to print the accepted output in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Just the output, as the command printed it.
+    #[default]
+    Raw,
+    /// A fenced shell code block with the command and its output.
+    Markdown,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "markdown" => Ok(Self::Markdown),
+            _ => bail!("expected one of raw, markdown"),
+        }
+    }
+}
+
+/// What to do when Enter is pressed without a command.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyEnter {
+    /// Stay, since it was most likely pressed by accident.
+    #[default]
+    Ignore,
+    /// Exit without printing anything, like Esc.
+    Abort,
+    /// Exit and print the empty output.
+    Accept,
+}
+
+impl FromStr for EmptyEnter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "abort" => Ok(Self::Abort),
+            "accept" => Ok(Self::Accept),
+            _ => bail!("expected one of ignore, abort, accept"),
+        }
+    }
+}
+
+impl Config {
+    /// Parses the process arguments, exiting early if help was requested.
+    pub fn from_args() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        let mut max_output_bytes = None;
+        let mut truncate = Truncate::default();
+        let mut truncate_marker = "[output truncated]".to_string();
+
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`.
+            let (flag, mut inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| anyhow!("missing value for {flag}"))
+            };
+
+            match flag.as_str() {
+                "--columns" => config.columns = Some(parse_value(&flag, value()?)?),
+                "--output-format" => config.output_format = parse_value(&flag, value()?)?,
+                "--timestamp-format" => config.timestamp_format = parse_value(&flag, value()?)?,
+                "--graphics" => {
+                    config.graphics = match value()?.as_str() {
+                        "auto" => Protocol::detect(),
+                        "none" => None,
+                        protocol => Some(parse_value(&flag, protocol.to_string())?),
+                    }
+                }
+                "--prefix" => config.prefix = Some(value()?),
+                "--template" => config.template = Some(Template::parse(&value()?)?),
+                "--ssh" => config.ssh = Some(value()?),
+                "--docker" | "--podman" => {
+                    config.container = Some(Container {
+                        engine: if flag == "--docker" {
+                            "docker"
+                        } else {
+                            "podman"
+                        },
+                        name: value()?,
+                    })
+                }
+                "--playlist" => config.playlist = Some(Playlist::load(Path::new(&value()?))?),
+                "--select-template" => config.select_template = Some(value()?),
+                "--reference-pattern" => {
+                    let pattern = value()?;
+                    let pattern = Regex::new(&pattern)
+                        .with_context(|| format!("invalid value for {flag}: {pattern:?}"))?;
+                    let groups: Vec<_> = pattern.capture_names().flatten().collect();
+                    if !groups.contains(&"path") || !groups.contains(&"line") {
+                        bail!("{flag} needs groups named path and line, like (?P<path>...)");
+                    }
+                    config.reference_pattern = pattern;
+                }
+                "--sample" => config.samples.push(parse_value(&flag, value()?)?),
+                "--formatter" => config.formatter = Some(value()?),
+                "--dry-run" => config.dry_run = true,
+                "--dry-run-flags" => {
+                    config.dry_run_flags = list(&value()?)
+                        .iter()
+                        .map(|pair| match pair.split_once('=') {
+                            Some((command, flag)) if !command.trim().is_empty() => {
+                                Ok((command.trim().to_string(), flag.trim().to_string()))
+                            }
+                            _ => {
+                                bail!("invalid value for {flag}: expected COMMAND=FLAG in {pair:?}")
+                            }
+                        })
+                        .collect::<Result<_>>()?
+                }
+                "--safe-preview" => config.safe_preview = true,
+                "--read-only-commands" => config.read_only_commands = list(&value()?),
+                "--mutating-commands" => config.mutating_commands = list(&value()?),
+                "--history-size" => config.history_size = parse_value(&flag, value()?)?,
+                "--history-dedup" => config.history_dedup = parse_value(&flag, value()?)?,
+                "--keylog" => config.keylog = Some(value()?.into()),
+                "--on-start" => config.on_start = Some(value()?),
+                "--on-accept" => config.on_accept = Some(value()?),
+                "--compact" => config.compact = true,
+                "--empty-enter" => config.empty_enter = parse_value(&flag, value()?)?,
+                "--print-on-exit" => config.print_on_exit = true,
+                "--no-focus-events" => config.focus_events = false,
+                "--cursor-style" => {
+                    config.cursor_style = Some(match value()?.as_str() {
+                        "block" => CursorShape::Block,
+                        "bar" => CursorShape::Line,
+                        "underline" => CursorShape::UnderScore,
+                        style => bail!(
+                            "invalid value for {flag}: {style:?}: expected one of block, bar, underline"
+                        ),
+                    })
+                }
+                "--cursor-color" => {
+                    let color = value()?;
+                    // It goes into an escape sequence as is.
+                    if color.is_empty() || color.contains(|c: char| c.is_control()) {
+                        bail!("invalid value for {flag}: {color:?}");
+                    }
+                    config.cursor_color = Some(color);
+                }
+                "--indicator-delay" => {
+                    config.indicator_delay = Duration::from_millis(parse_value(&flag, value()?)?)
+                }
+                "--highlight-changes" => {
+                    config.highlight_changes =
+                        Some(Duration::from_millis(parse_value(&flag, value()?)?))
+                }
+                "--highlight-color" => {
+                    let color = value()?;
+                    config.highlight_color = parse_color(&color)
+                        .with_context(|| format!("invalid value for {flag}: {color:?}"))?;
+                }
+                "--page-size" => config.page_size = Some(parse_value(&flag, value()?)?),
+                "--max-line-width" => config.max_line_width = Some(parse_value(&flag, value()?)?),
+                "--ellipsis" => config.ellipsis = value()?,
+                "--null-delimited" => config.null_delimited = true,
+                "--max-output-bytes" => max_output_bytes = Some(parse_value(&flag, value()?)?),
+                "--truncate" => truncate = parse_value(&flag, value()?)?,
+                "--truncate-marker" => truncate_marker = value()?,
+                "--min-update-interval" => {
+                    config.min_update_interval =
+                        Duration::from_millis(parse_value(&flag, value()?)?)
+                }
+                "-h" | "--help" => {
+                    print!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => bail!("unknown argument: {flag}\n\n{USAGE}"),
+            }
+        }
+
+        config.output_limit = max_output_bytes.map(|bytes| Limit {
+            bytes,
+            truncate,
+            marker: truncate_marker,
+        });
+        if config.ssh.is_some() && config.container.is_some() {
+            bail!("--ssh can't be used together with --docker or --podman");
+        }
+        if config.template.is_some() && config.playlist.is_some() {
+            bail!("--template and --playlist can't be used together");
+        }
+        Ok(config)
+    }
+}
+
+/// Parses a colour name like `red`, or a hex colour like `#ff8700`.
+fn parse_color(s: &str) -> Result<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        // Checked first, as slicing multi-byte characters would panic.
+        if hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let channel = |i| u8::from_str_radix(&hex[i..i + 2], 16);
+            return Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        bail!("expected #rrggbb");
+    }
+    Ok(match s {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "darkgray" => Color::DarkGray,
+        "white" => Color::White,
+        _ => bail!("expected a colour like yellow or #rrggbb"),
+    })
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn parse_value<T>(flag: &str, value: String) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    value
+        .parse()
+        .map_err(Into::<anyhow::Error>::into)
+        .with_context(|| format!("invalid value for {flag}: {value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Color::Rgb(255, 128, 0));
+        assert_eq!(parse_color("cyan").unwrap(), Color::Cyan);
+        for invalid in ["#aébcd", "#+f+f+f", "#fff", "teal"] {
+            assert!(parse_color(invalid).is_err(), "{invalid}");
+        }
+    }
+}
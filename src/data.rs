@@ -0,0 +1,32 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// The directory to keep data like the history in, following the XDG base
+/// directory spec, or `None` if there is no home directory.
+///
+/// It is created if needed and checked to be writable, so that features
+/// writing to it can be turned off on startup rather than failing on every
+/// write.
+pub fn dir() -> io::Result<Option<PathBuf>> {
+    let Some(data) = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+    else {
+        return Ok(None);
+    };
+    let dir = data.join("live-preview");
+    let not_writable =
+        |e: io::Error| io::Error::new(e.kind(), format!("{} isn't writable: {e}", dir.display()));
+
+    fs::create_dir_all(&dir).map_err(not_writable)?;
+    // Only actually writing shows whether it works, as things like
+    // read-only mounts don't show in the permissions.
+    let probe = dir.join(format!(".write-test-{}", process::id()));
+    fs::File::create(&probe).map_err(not_writable)?;
+    fs::remove_file(&probe).map_err(not_writable)?;
+    Ok(Some(dir))
+}
@@ -0,0 +1,132 @@
+use crate::shell;
+
+/// Commands with a flag that makes them only show what they would do, used
+/// with `--dry-run`.
+pub const DRY_RUN_FLAGS: &[(&str, &str)] = &[
+    ("ansible-playbook", "--check"),
+    ("cargo publish", "--dry-run"),
+    ("git add", "--dry-run"),
+    ("git clean", "--dry-run"),
+    ("git push", "--dry-run"),
+    ("git rm", "--dry-run"),
+    ("kubectl apply", "--dry-run=client"),
+    ("kubectl delete", "--dry-run=client"),
+    ("make", "--dry-run"),
+    ("npm publish", "--dry-run"),
+    ("rsync", "--dry-run"),
+];
+
+/// A command with dry-run flags added to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRun {
+    pub command: String,
+    /// The flags that were added.
+    pub flags: Vec<String>,
+}
+
+/// A flag in a command that turns its dry-run flag off again, like
+/// `--no-dry-run`, so adding the flag doesn't make it safe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cancelled {
+    pub flag: String,
+}
+
+/// Adds the dry-run flag right after each command in `command` that has
+/// one, like `rsync -av a b` to `rsync --dry-run -av a b`.
+///
+/// Commands can consist of several words like `git push`, and ones that
+/// already have their flag are left alone. Returns `None` if there was
+/// nothing to add, and an error if a command has a flag that cancels its
+/// dry-run flag, as the added flag comes first and loses.
+pub fn inject(command: &str, flags: &[(String, String)]) -> Result<Option<DryRun>, Cancelled> {
+    let Ok(tokens) = shell::tokenize(command) else {
+        return Ok(None);
+    };
+    // Where to add which flag, in order.
+    let mut insertions = vec![];
+    for simple in shell::commands(&tokens) {
+        let words: Vec<_> = shell::word_tokens(simple).collect();
+        let found = flags.iter().find(|(name, _)| {
+            let expected: Vec<_> = name.split_whitespace().collect();
+            !expected.is_empty()
+                && words.len() >= expected.len()
+                && words.iter().zip(&expected).all(|(w, e)| w.value == *e)
+        });
+        let Some((name, flag)) = found else {
+            continue;
+        };
+        if let Some(word) = words.iter().find(|w| cancels(&w.value, flag)) {
+            return Err(Cancelled {
+                flag: word.value.clone(),
+            });
+        }
+        if words.iter().any(|w| w.value == *flag) {
+            continue;
+        }
+        let last = words[name.split_whitespace().count() - 1];
+        insertions.push((last.span.end, flag.clone()));
+    }
+    if insertions.is_empty() {
+        return Ok(None);
+    }
+
+    let mut injected = String::with_capacity(command.len());
+    let mut start = 0;
+    for (offset, flag) in &insertions {
+        injected += &command[start..*offset];
+        injected.push(' ');
+        injected += flag;
+        start = *offset;
+    }
+    injected += &command[start..];
+    Ok(Some(DryRun {
+        command: injected,
+        flags: insertions.into_iter().map(|(_, flag)| flag).collect(),
+    }))
+}
+
+/// Whether `word` turns the long option `flag` off, like `--no-dry-run` or
+/// `--dry-run=none` for `--dry-run=client`.
+fn cancels(word: &str, flag: &str) -> bool {
+    let Some(name) = flag.split('=').next().and_then(|f| f.strip_prefix("--")) else {
+        return false;
+    };
+    if word == format!("--no-{name}") {
+        return true;
+    }
+    word.strip_prefix("--")
+        .and_then(|w| w.strip_prefix(name))
+        .and_then(|w| w.strip_prefix('='))
+        .is_some_and(|value| matches!(value, "none" | "false"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Vec<(String, String)> {
+        DRY_RUN_FLAGS
+            .iter()
+            .map(|(name, flag)| (name.to_string(), flag.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn adds_flag_after_command() {
+        let dry_run = inject("rsync -av a b", &defaults()).unwrap().unwrap();
+        assert_eq!(dry_run.command, "rsync --dry-run -av a b");
+    }
+
+    #[test]
+    fn leaves_rm_alone() {
+        assert_eq!(inject("rm -rf f", &defaults()), Ok(None));
+    }
+
+    #[test]
+    fn refuses_cancelled_flags() {
+        for command in ["git push --no-dry-run", "kubectl apply --dry-run=none -f x"] {
+            assert!(inject(command, &defaults()).is_err(), "{command}");
+        }
+        assert!(inject("kubectl apply --dry-run=server -f x", &defaults()).is_ok());
+    }
+}
@@ -0,0 +1,126 @@
+use unicode_width::UnicodeWidthChar;
+
+/// A single line of editable text with a cursor.
+///
+/// The cursor counts characters rather than bytes, so multi-byte input can
+/// be edited without landing in the middle of a character.
+#[derive(Debug, Default, Clone)]
+pub struct Editor {
+    text: String,
+    cursor: usize,
+}
+
+impl Editor {
+    /// Creates an editor with the cursor at the end of the text.
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        Self {
+            cursor: text.chars().count(),
+            text,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Moves the cursor one character to the left, returning whether it moved.
+    pub fn left(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        true
+    }
+
+    /// Moves the cursor one character to the right, returning whether it moved.
+    pub fn right(&mut self) -> bool {
+        if self.cursor == self.text.chars().count() {
+            return false;
+        }
+        self.cursor += 1;
+        true
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let offset = self.offset();
+        self.text.insert(offset, c);
+        self.cursor += 1;
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        let offset = self.offset();
+        self.text.insert_str(offset, s);
+        self.cursor += s.chars().count();
+    }
+
+    /// Deletes the character before the cursor, returning whether there was one.
+    pub fn delete(&mut self) -> bool {
+        if !self.left() {
+            return false;
+        }
+        let offset = self.offset();
+        self.text.remove(offset);
+        true
+    }
+
+    /// The text before the cursor.
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.offset()]
+    }
+
+    /// The text as it should be shown, with control characters in caret
+    /// notation like `^I`.
+    pub fn display(&self) -> String {
+        let mut display = String::with_capacity(self.text.len());
+        for c in self.text.chars() {
+            match caret(c) {
+                Some(c) => {
+                    display.push('^');
+                    display.push(c);
+                }
+                None => display.push(c),
+            }
+        }
+        display
+    }
+
+    /// The text with every character hidden behind a `•`.
+    pub fn masked(&self) -> String {
+        "•".repeat(self.text.chars().count())
+    }
+
+    /// Where the cursor is in the masked text, in terminal columns.
+    pub fn masked_cursor_column(&self) -> u16 {
+        self.cursor as u16
+    }
+
+    /// The width of the text before the cursor in terminal columns.
+    pub fn cursor_column(&self) -> u16 {
+        self.text
+            .chars()
+            .take(self.cursor)
+            .map(|c| match caret(c) {
+                Some(_) => 2,
+                None => c.width().unwrap_or(0) as u16,
+            })
+            .sum()
+    }
+
+    /// The byte offset of the cursor in the text.
+    fn offset(&self) -> usize {
+        self.text
+            .char_indices()
+            .nth(self.cursor)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+}
+
+/// The letter for a control character in caret notation.
+fn caret(c: char) -> Option<char> {
+    match c {
+        '\0'..='\x1f' => Some((c as u8 + b'@') as char),
+        '\x7f' => Some('?'),
+        _ => None,
+    }
+}
@@ -0,0 +1,174 @@
+use std::{env, fmt, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Result};
+use tui::layout::Rect;
+
+/// Image formats recognised in command output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+}
+
+impl ImageFormat {
+    /// Recognises an image by the magic number at its start.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.starts_with(b"\xff\xd8\xff") {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Png => write!(f, "PNG"),
+            Self::Jpeg => write!(f, "JPEG"),
+            Self::Gif => write!(f, "GIF"),
+        }
+    }
+}
+
+/// An image a command printed.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub format: ImageFormat,
+    pub data: Arc<[u8]>,
+}
+
+/// Terminal graphics protocols to show images with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Kitty's graphics protocol, which only takes PNGs as they are.
+    Kitty,
+    /// iTerm2's inline images, also supported by WezTerm.
+    Iterm2,
+}
+
+impl Protocol {
+    /// Guesses the protocol the terminal supports from its environment.
+    pub fn detect() -> Option<Self> {
+        let var = |name| env::var(name).unwrap_or_default();
+        if env::var_os("KITTY_WINDOW_ID").is_some() || var("TERM").contains("kitty") {
+            Some(Self::Kitty)
+        } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") {
+            Some(Self::Iterm2)
+        } else {
+            None
+        }
+    }
+
+    /// The escape sequence to show an image in `area`, with the cursor at its
+    /// top left corner. Images are scaled to the width of the area, or to its
+    /// height if they are too tall.
+    ///
+    /// Returns `None` if the protocol can't show the format.
+    pub fn show(self, image: &Image, area: Rect) -> Option<String> {
+        let data = base64::encode(&image.data);
+        match self {
+            Self::Kitty => {
+                if image.format != ImageFormat::Png {
+                    return None;
+                }
+                // Give only one dimension so that the aspect ratio is kept,
+                // guessing that cells are twice as high as they are wide.
+                // The size comes from the command's output, so it can be
+                // anything and is calculated without overflowing.
+                let too_tall = png_size(&image.data).is_some_and(|(width, height)| {
+                    u64::from(height) * u64::from(area.width) / u64::from(width) / 2
+                        > u64::from(area.height)
+                });
+                let size = if too_tall {
+                    format!("r={}", area.height)
+                } else {
+                    format!("c={}", area.width)
+                };
+                // The data has to be sent in chunks of at most 4096 bytes.
+                // Replies are suppressed so they don't end up as input.
+                let chunks: Vec<_> = data.as_bytes().chunks(4096).collect();
+                let mut escape = String::new();
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let more = u8::from(i + 1 < chunks.len());
+                    let chunk = std::str::from_utf8(chunk).expect("base64 is ASCII");
+                    if i == 0 {
+                        escape += &format!("\x1b_Ga=T,f=100,{size},C=1,q=2,m={more};{chunk}\x1b\\");
+                    } else {
+                        escape += &format!("\x1b_Gm={more};{chunk}\x1b\\");
+                    }
+                }
+                Some(escape)
+            }
+            Self::Iterm2 => Some(format!(
+                "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{data}\x07",
+                image.data.len(),
+                area.width,
+                area.height,
+            )),
+        }
+    }
+
+    /// The escape sequence to remove images shown before, if the protocol
+    /// needs one. Otherwise they disappear once the text under them is drawn
+    /// again.
+    pub fn delete(self) -> Option<&'static str> {
+        match self {
+            Self::Kitty => Some("\x1b_Ga=d,q=2\x1b\\"),
+            Self::Iterm2 => None,
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "kitty" => Ok(Self::Kitty),
+            "iterm2" => Ok(Self::Iterm2),
+            _ => bail!("expected one of auto, kitty, iterm2, none"),
+        }
+    }
+}
+
+/// The width and height of a PNG, from its header.
+fn png_size(data: &[u8]) -> Option<(u32, u32)> {
+    let header = data.get(16..24)?;
+    let width = u32::from_be_bytes(header[..4].try_into().ok()?);
+    let height = u32::from_be_bytes(header[4..].try_into().ok()?);
+    (width > 0).then_some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start of a PNG file with the given size in its header.
+    fn png(width: u32, height: u32) -> Image {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        Image {
+            format: ImageFormat::Png,
+            data: data.into(),
+        }
+    }
+
+    #[test]
+    fn huge_png_header_fits_height() {
+        let escape = Protocol::Kitty.show(&png(1, u32::MAX), Rect::new(0, 0, 80, 24));
+        assert!(escape.unwrap().contains("r=24"));
+    }
+
+    #[test]
+    fn wide_png_fits_width() {
+        let escape = Protocol::Kitty.show(&png(1000, 100), Rect::new(0, 0, 80, 24));
+        assert!(escape.unwrap().contains("c=80"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{config::Config, data};
+
+/// Which repeated commands to leave out of the history.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dedup {
+    /// Keep every command.
+    None,
+    /// Collapse runs of the same command, like `HISTCONTROL=ignoredups`.
+    #[default]
+    Consecutive,
+    /// Keep only the most recent of each command, like `erasedups`.
+    All,
+}
+
+impl FromStr for Dedup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "consecutive" => Ok(Self::Consecutive),
+            "all" => Ok(Self::All),
+            _ => bail!("expected one of none, consecutive, all"),
+        }
+    }
+}
+
+/// Accepted commands from earlier runs, oldest first, and where the user is
+/// in them.
+#[derive(Debug, Default)]
+pub struct History {
+    /// The history file, unless the history isn't kept.
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    /// The entry being shown, if the user is going through the history.
+    position: Option<usize>,
+    /// The input from before going through the history.
+    draft: String,
+}
+
+impl History {
+    /// Loads the history, which is empty if there is none yet.
+    ///
+    /// Fails if the history file can't be written, in which case the
+    /// history is best turned off.
+    pub fn load(config: &Config) -> io::Result<Self> {
+        if config.history_size == 0 {
+            return Ok(Self::default());
+        }
+        let Some(dir) = data::dir()? else {
+            return Ok(Self::default());
+        };
+        let path = dir.join("history");
+        // The directory being writable doesn't help with a read-only file.
+        match fs::OpenOptions::new().append(true).open(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{} isn't writable: {e}", path.display()),
+                ));
+            }
+            _ => {}
+        }
+        Ok(Self {
+            entries: read(&path, config)?,
+            path: Some(path),
+            ..Self::default()
+        })
+    }
+
+    /// Adds a command to the history file.
+    ///
+    /// The file is read again first so that commands accepted in other
+    /// instances in the meantime are kept.
+    pub fn append(&self, command: &str, config: &Config) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if command.trim().is_empty() {
+            return Ok(());
+        }
+        let mut entries = read(path, config)?;
+        push(&mut entries, command.to_string(), config);
+
+        let mut contents = String::new();
+        for entry in &entries {
+            contents += &encode(entry);
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+
+    /// Goes back one entry, returning it.
+    pub fn previous(&mut self, input: &str) -> Option<&str> {
+        let position = match self.position {
+            Some(0) => return None,
+            Some(position) => position - 1,
+            None => {
+                self.draft = input.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// Goes forward one entry, returning it, or the input from before going
+    /// through the history after the last one.
+    pub fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(&self.entries[position])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+/// Reads the history file, applying the current settings to it.
+fn read(path: &Path, config: &Config) -> io::Result<Vec<String>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut entries = vec![];
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        push(&mut entries, decode(line), config);
+    }
+    Ok(entries)
+}
+
+/// Adds an entry, dropping duplicates and the oldest entries beyond the
+/// size limit.
+fn push(entries: &mut Vec<String>, entry: String, config: &Config) {
+    match config.history_dedup {
+        Dedup::None => {}
+        Dedup::Consecutive if entries.last() == Some(&entry) => return,
+        Dedup::Consecutive => {}
+        Dedup::All => entries.retain(|e| *e != entry),
+    }
+    entries.push(entry);
+    let excess = entries.len().saturating_sub(config.history_size);
+    entries.drain(..excess);
+}
+
+/// Escapes newlines, which commands can contain, to keep one entry per line.
+fn encode(entry: &str) -> String {
+    entry.replace('\\', r"\\").replace('\n', r"\n")
+}
+
+fn decode(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                entry.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                entry.push('\\');
+            }
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
@@ -0,0 +1,83 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::mpsc::{self, Sender},
+    thread,
+    time::Instant,
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+/// Records key presses to a file with `--keylog`, one per line along with
+/// the seconds since startup, like `1.250 Ctrl+t`.
+///
+/// The file is written on a thread of its own, so that a slow disk never
+/// holds up input.
+pub struct KeyLog {
+    lines: Sender<String>,
+    start: Instant,
+}
+
+impl KeyLog {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (lines, rx) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            // Flushing every line keeps the log complete if the interface
+            // crashes, which is when it is most useful.
+            for line in rx {
+                if writeln!(file, "{line}").and_then(|_| file.flush()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            lines,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&self, key: &KeyEvent) {
+        let seconds = self.start.elapsed().as_secs_f64();
+        // The writer only stops if the file can't be written anymore, and
+        // there is nobody to tell about it.
+        let _ = self.lines.send(format!("{seconds:.3} {}", describe(key)));
+    }
+}
+
+/// A key as it would be written in the help, like `Ctrl+Shift+Up` or `a`.
+///
+/// Characters that could be mistaken for something else are spelled out or
+/// escaped, so that every line means exactly one key.
+fn describe(key: &KeyEvent) -> String {
+    let mut description = String::new();
+    for (modifier, name) in [
+        (KeyModifiers::CONTROL, "Ctrl+"),
+        (KeyModifiers::ALT, "Alt+"),
+        (KeyModifiers::SHIFT, "Shift+"),
+        (KeyModifiers::SUPER, "Super+"),
+    ] {
+        if key.modifiers.contains(modifier) {
+            description += name;
+        }
+    }
+    match key.code {
+        KeyCode::Char(' ') => description += "Space",
+        KeyCode::Char(c) if c.is_control() || c.is_whitespace() => {
+            description += &format!("{:?}", c)
+        }
+        KeyCode::Char(c) => description.push(c),
+        KeyCode::F(n) => description += &format!("F{n}"),
+        KeyCode::BackTab => description += "BackTab",
+        KeyCode::PageUp => description += "PageUp",
+        KeyCode::PageDown => description += "PageDown",
+        code => description += &format!("{code:?}"),
+    }
+    match key.kind {
+        KeyEventKind::Press => {}
+        KeyEventKind::Repeat => description += " (repeat)",
+        KeyEventKind::Release => description += " (release)",
+    }
+    description
+}
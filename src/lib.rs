@@ -0,0 +1,6 @@
+//! The parts of live-preview that are useful on their own.
+//!
+//! [`shell`] splits shell commands into tokens and pipelines without running
+//! or expanding anything.
+
+pub mod shell;
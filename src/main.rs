@@ -1,110 +1,1220 @@
-use std::io;
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    panic,
+    process::{ExitStatus, Output, Stdio},
+    sync::{atomic, Arc},
+    time::SystemTime,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode},
-    execute,
+    cursor::{MoveTo, RestorePosition, SavePosition, SetCursorShape},
+    event::{
+        self, DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture,
+        Event, EventStream, KeyCode, KeyModifiers,
+    },
+    execute, queue,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use futures::{future::OptionFuture, StreamExt};
 use tokio::{
-    process, select,
-    sync::mpsc::{channel, Receiver, Sender},
+    select,
+    sync::{
+        mpsc::{channel, Sender},
+        watch,
+    },
+    task::JoinError,
+    time::{sleep_until, Instant},
 };
 use tui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    widgets::{Block, Borders, Paragraph, Wrap},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
     Frame, Terminal,
 };
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+// The shell parser is in the library, and this makes it `crate::shell` for
+// the rest of the binary.
+use live_preview::shell;
+
+use crate::{
+    child::{child_handler, Cmd, Exec, Update},
+    complete::Candidate,
+    config::{Config, EmptyEnter, OutputFormat},
+    dry_run::DryRun,
+    editor::Editor,
+    graphics::Image,
+    history::History,
+    keylog::KeyLog,
+    picker::{FilePicker, Picker},
+    playlist::Playlist,
+    prompt::{Prompt, PromptKind},
+    references::Reference,
+    safety::Safety,
+    samples::Sample,
+    shell::Mistake,
+    template::Template,
+};
+
+mod child;
+mod complete;
+mod config;
+mod data;
+mod dry_run;
+mod editor;
+mod graphics;
+mod history;
+mod keylog;
+mod output;
+mod picker;
+mod playlist;
+mod prompt;
+mod references;
+mod safety;
+mod samples;
+mod template;
+mod timestamps;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     #[cfg(feature = "tokio-console")]
     console_subscriber::init();
 
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let config = Arc::new(Config::from_args()?);
+    if let Some(container) = &config.container {
+        child::check_container(container)?;
+    }
+
+    // Restore the terminal before the panic message is printed, otherwise it
+    // ends up garbled in the alternate screen and the shell is left in raw
+    // mode. This also covers panics in spawned tasks.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new({
+        let config = config.clone();
+        move |info| {
+            let _ = restore_terminal(&config);
+            default_hook(info);
+        }
+    }));
+
+    enter_terminal(&config)?;
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
-    enable_raw_mode()?;
 
-    let output = event_loop(&mut terminal).await?;
+    let exit = event_loop(&mut terminal, config.clone()).await;
 
-    execute!(stdout, LeaveAlternateScreen, DisableMouseCapture)?;
-    disable_raw_mode()?;
+    restore_terminal(&config)?;
+    let Exit {
+        accepted,
+        command,
+        output,
+        sensitive,
+        history,
+    } = exit?;
+
+    // This goes to stderr to stay out of the way of the accepted output.
+    if config.print_on_exit && !command.is_empty() {
+        if !sensitive {
+            eprintln!("$ {command}");
+        }
+        if !output.is_empty() {
+            eprint!("{output}");
+            if !output.ends_with('\n') {
+                eprintln!();
+            }
+        }
+    }
+
+    if accepted {
+        // The interface is gone by now, so failures go to stderr.
+        // Secrets don't belong in the history file.
+        if !sensitive {
+            if let Err(e) = history.append(&command, &config) {
+                eprintln!("Failed to save history: {e}");
+            }
+        }
 
-    if let Some(o) = output {
-        if !o.is_empty() {
-            print!("{o}");
+        match config.output_format {
+            OutputFormat::Raw if !output.is_empty() => print!("{output}"),
+            OutputFormat::Raw => {}
+            OutputFormat::Markdown => print!("{}", output::markdown(&command, &output)),
+        }
+
+        if let Some(hook) = &config.on_accept {
+            match child::hook(hook, &command, &output, &config).await {
+                Ok(result) if result.status.success() => {}
+                Ok(result) => eprintln!("on-accept hook failed: {}", failure(&result)),
+                Err(e) => eprintln!("Failed to run on-accept hook: {e}"),
+            }
         }
     }
 
     Ok(())
 }
 
+fn enter_terminal(config: &Config) -> Result<()> {
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    if config.focus_events {
+        execute!(io::stdout(), EnableFocusChange)?;
+    }
+    if let Some(style) = config.cursor_style {
+        execute!(io::stdout(), SetCursorShape(style))?;
+    }
+    if let Some(color) = &config.cursor_color {
+        write!(io::stdout(), "\x1b]12;{color}\x07")?;
+    }
+    enable_raw_mode()?;
+    Ok(())
+}
+
+fn restore_terminal(config: &Config) -> Result<()> {
+    execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableFocusChange
+    )?;
+    // Only undo what was changed, as the shell may have set a cursor of its
+    // own.
+    if config.cursor_style.is_some() {
+        write!(io::stdout(), "\x1b[0 q")?;
+    }
+    if config.cursor_color.is_some() {
+        write!(io::stdout(), "\x1b]112\x07")?;
+    }
+    io::stdout().flush()?;
+    disable_raw_mode()?;
+    Ok(())
+}
+
+/// Hands the terminal over to an interactive program like a pager, and takes
+/// it back once the program exits.
+///
+/// This blocks, which keeps crossterm from reading input meant for the
+/// program, as it only does so while events are being waited for.
+fn suspend(
+    terminal: &mut Term,
+    config: &Config,
+    command: &mut std::process::Command,
+) -> Result<ExitStatus> {
+    restore_terminal(config)?;
+    let status = command.status();
+    enter_terminal(config)?;
+    // Whatever the program left on screen has to go.
+    terminal.clear()?;
+    Ok(status?)
+}
+
 #[derive(Debug, Default)]
 struct State {
-    cursor: u16,
-    input: String,
+    input: Editor,
+    output: String,
+    /// All of stdout once the command is done, for piping it on.
+    stdout: Option<String>,
+    /// The command the output is from, which differs from the input while the
+    /// output is stale.
+    output_command: String,
+    mode: Mode,
+    running: Running,
+    /// Something to let the user know, shown in the status line.
+    message: Option<String>,
+    /// How safe the command looks to run live, with `--safe-preview`.
+    safety: Option<Safety>,
+    /// Fields to fill in instead of typing the whole command.
+    template: Option<Template>,
+    /// The output line the view follows.
+    current_line: usize,
+    /// The first output line in view.
+    scroll: usize,
+    line_numbers: LineNumbers,
+    /// The image the command printed, shown with `--graphics`.
+    image: Option<Image>,
+    /// Whether an image is on screen, which may need removing.
+    image_shown: bool,
+    /// Where the output went in the last frame.
+    output_area: Rect,
+    history: History,
+    /// How the command last exited.
+    status: Option<ExitStatus>,
+    /// Whether to also show the output of the command run without a shell.
+    split: bool,
+    direct_output: String,
+    direct_status: Option<ExitStatus>,
+    /// The command as the formatter formatted it, or why it couldn't.
+    formatted: Option<Result<String, String>>,
+    playlist: Option<Playlist>,
+    /// The input from before it was tidied, to go back to.
+    undo: Option<Editor>,
+    /// Whether to rewrite timestamps in the output.
+    timestamps: bool,
+    /// The output as shown, if it differs from what the command printed.
+    transformed: Option<String>,
+    /// The command as it runs with `--dry-run`, if there are flags to add.
+    dry_run: Option<DryRun>,
+    /// Whether the user turned dry runs off.
+    dry_run_off: bool,
+    /// A flag in the command that cancels its dry-run flag, so it isn't run
+    /// live with `--dry-run`.
+    dry_run_cancelled: Option<String>,
+    /// Whether the terminal is in the background, so there is no point in
+    /// redrawing.
+    unfocused: bool,
+    /// Whether to show the whole of the current output line.
+    show_line: bool,
+    /// Whether the output is mostly terminal control sequences.
+    control_only: bool,
+    /// Whether the command has secrets in it, so it is masked on screen.
+    sensitive: bool,
+    /// Until when to highlight each output line, for the ones that changed
+    /// since the command last ran.
+    changed: Vec<Option<Instant>>,
+    /// The last command that finished, and its output as shown.
+    finished: Option<(String, String)>,
+    /// The file references in the output, while moving through them.
+    references: Vec<Reference>,
+    /// The earlier commands whose output is piped into this one, in order.
+    piped: Vec<Piped>,
+    /// A quoting or redirection mistake in the command.
+    mistake: Option<Mistake>,
+}
+
+/// A command whose output was kept to pipe into the next one.
+#[derive(Debug)]
+struct Piped {
+    command: String,
+    stdout: Arc<str>,
+}
+
+impl State {
+    /// The editor that keys act on, which is the active template field if
+    /// there is a template.
+    fn editor(&mut self) -> &mut Editor {
+        match &mut self.template {
+            Some(template) => template.active(),
+            None => &mut self.input,
+        }
+    }
+
+    /// When the next changed line stops being highlighted.
+    fn next_unhighlight(&self) -> Option<Instant> {
+        self.changed.iter().flatten().min().copied()
+    }
+
+    /// The output as shown.
+    fn shown_output(&self) -> &str {
+        self.transformed.as_deref().unwrap_or(&self.output)
+    }
+
+    /// The command to run.
+    fn command(&self) -> String {
+        match &self.template {
+            Some(template) => template.command(),
+            None => self.input.text().to_string(),
+        }
+    }
+
+    /// The command to run live, which can have dry-run flags added.
+    fn live_command(&self) -> String {
+        match &self.dry_run {
+            Some(dry_run) => dry_run.command.clone(),
+            None => self.command(),
+        }
+    }
+
+    /// What to feed the command on stdin, which is the stdout of the last
+    /// piped command.
+    fn stdin(&self) -> Option<Arc<str>> {
+        self.piped.last().map(|piped| piped.stdout.clone())
+    }
+
+    /// The command along with the ones piped into it, as a pipeline that
+    /// gets the same output when run again.
+    fn pipeline(&self) -> String {
+        let mut commands: Vec<_> = self.piped.iter().map(|p| p.command.clone()).collect();
+        commands.push(self.command());
+        commands.join(" | ")
+    }
+}
+
+/// How to number output lines in the gutter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LineNumbers {
+    #[default]
+    Off,
+    Absolute,
+    /// Distances from the current line, like Vim's `relativenumber`.
+    Relative,
+}
+
+impl LineNumbers {
+    fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Absolute,
+            Self::Absolute => Self::Relative,
+            Self::Relative => Self::Off,
+        }
+    }
+}
+
+/// Whether a command is running, and if that is shown yet.
+///
+/// The indicator is only shown after a short delay, so that fast commands
+/// don't make it flicker.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Running {
+    #[default]
+    No,
+    /// Running, but the indicator isn't shown until the deadline.
+    Quietly(Instant),
+    Visibly,
+}
+
+impl Running {
+    fn indicator_deadline(self) -> Option<Instant> {
+        match self {
+            Self::Quietly(deadline) => Some(deadline),
+            _ => None,
+        }
+    }
+}
+
+/// What keys currently act on.
+#[derive(Debug, Default)]
+enum Mode {
+    /// Editing the command.
+    #[default]
+    Normal,
+    /// Choosing a file path to insert into the command.
+    FilePicker(FilePicker),
+    /// Typing into a popup.
+    Prompt(Prompt),
+    /// Choosing one of several completions for the word before the cursor.
+    Completion(Picker<Candidate>),
+    /// Choosing an output line to load into the input.
+    SelectLine,
+    /// Choosing a file reference in the output to open, by its index.
+    References(usize),
+    /// Choosing a kind of sample value to insert.
+    Samples(Picker<Sample>),
+}
+
+/// The command and output when the user quit.
+#[derive(Debug)]
+struct Exit {
+    /// Whether the user accepted the output with Enter, rather than aborting.
+    accepted: bool,
+    command: String,
     output: String,
+    /// Whether the command has secrets in it, so it must not be kept.
+    sensitive: bool,
+    /// Where to add the command to if accepted.
+    history: History,
 }
 
-async fn event_loop(
-    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-) -> Result<Option<String>> {
-    let mut state = State::default();
+async fn event_loop(terminal: &mut Term, config: Arc<Config>) -> Result<Exit> {
+    let mut state = State {
+        template: config.template.clone(),
+        playlist: config.playlist.clone(),
+        ..State::default()
+    };
+    match History::load(&config) {
+        Ok(history) => state.history = history,
+        Err(e) => state.message = Some(format!("History is off: {e}")),
+    }
+    let keylog = match &config.keylog {
+        Some(path) => Some(
+            KeyLog::create(path)
+                .with_context(|| format!("failed to create key log {}", path.display()))?,
+        ),
+        None => None,
+    };
     let mut event_stream = EventStream::new();
+    // Whether the next key is to be inserted as is.
+    let mut verbatim = false;
     let (cmd_tx, cmd_rx) = channel::<Cmd>(1);
-    let (output_tx, mut output_rx) = channel::<Option<String>>(1);
+    let (output_tx, mut output_rx) = watch::channel(Update::default());
+    let (direct_cmd_tx, direct_cmd_rx) = channel::<Cmd>(1);
+    let (direct_output_tx, mut direct_output_rx) = watch::channel(Update::default());
+    // Formatted commands, along with the input they are for.
+    let (formatted_tx, mut formatted_rx) = channel::<(String, io::Result<Output>)>(1);
+    let handlers = Handlers {
+        shell: cmd_tx,
+        direct: direct_cmd_tx,
+        formatted: formatted_tx,
+    };
 
-    terminal.draw(|f| draw_ui(f, &state.cursor, &state.input, &state.output))?;
+    draw(terminal, &mut state, &config)?;
 
-    tokio::spawn(child_handler(cmd_rx, output_tx));
+    // Whether the child handlers stopped, after which the task can't be
+    // polled again.
+    let mut child_stopped = false;
+    let mut child_task = tokio::spawn({
+        let config = config.clone();
+        async move {
+            select! {
+                result = child_handler(cmd_rx, output_tx, config.clone(), Exec::Shell) => result,
+                result = child_handler(direct_cmd_rx, direct_output_tx, config, Exec::Direct) => result,
+            }
+        }
+    });
+    // Results of sub-commands run for their output.
+    let (sub_tx, mut sub_rx) = channel::<io::Result<Output>>(1);
+    // Results of hooks, which only matter if they failed.
+    let (hook_tx, mut hook_rx) = channel::<io::Result<Output>>(1);
+
+    if let Some(hook) = config.on_start.clone() {
+        let config = config.clone();
+        tokio::spawn(async move {
+            let _ = hook_tx
+                .send(child::hook(&hook, "", "", &config).await)
+                .await;
+        });
+    }
+
+    if let Some(playlist) = &state.playlist {
+        state.input = Editor::new(playlist.current());
+        input_changed(&handlers, &mut state, &config).await?;
+        draw(terminal, &mut state, &config)?;
+    }
 
     loop {
         select! {
-            Some(output) = output_rx.recv() => {
-                if let Some(s) = output {
-                    state.output = s;
-                } else {
-                    state.output.clear();
+            Some(()) = OptionFuture::from(state.running.indicator_deadline().map(sleep_until)) => {
+                state.running = Running::Visibly;
+                draw(terminal, &mut state, &config)?;
+            },
+            Some(()) = OptionFuture::from(state.next_unhighlight().map(sleep_until)) => {
+                let now = Instant::now();
+                for deadline in &mut state.changed {
+                    *deadline = deadline.filter(|&d| d > now);
                 }
-                terminal.draw(|f| draw_ui(f, &state.cursor, &state.input, &state.output))?;
+                draw(terminal, &mut state, &config)?;
+            },
+            result = &mut child_task, if !child_stopped => {
+                child_stopped = true;
+                child_exited(&mut state, result)?;
+                draw(terminal, &mut state, &config)?;
             },
-            maybe_action = input_handler(&mut event_stream) => {
-                match maybe_action {
-                    Some(Action::Done) => {
-                        cmd_tx.send(Cmd::Done).await?;
-                        return Ok(Some(state.output))
-                    },
-                    Some(Action::Abort) => {
-                        cmd_tx.send(Cmd::Done).await?;
-                        return Ok(None)
-                    },
-                    Some(Action::CursorLeft) => if state.cursor > 0 {
-                        state.cursor -= 1
-                    },
-                    Some(Action::CursorRight) => if state.cursor < state.input.len() as u16 {
-                        state.cursor += 1
-                    },
-                    Some(Action::Delete) => if state.cursor > 0 {
-                        state.cursor -= 1;
-                        state.input.remove(state.cursor as usize);
-                        cmd_tx.send(Cmd::Input(state.input.clone())).await?;
-                    },
-                    Some(Action::Type(chr)) => {
-                        state.input.insert(state.cursor as usize, chr);
-                        state.cursor += 1;
-                        cmd_tx.send(Cmd::Input(state.input.clone())).await?;
-                    },
-                    // Take it off the channel to avoid deadlocking.
-                    None => {},
+            Ok(()) = output_rx.changed() => {
+                let update = output_rx.borrow_and_update();
+                let running = if update.done { Running::No } else { state.running };
+                // Commands that refresh themselves often print the same thing
+                // again, which isn't worth a redraw. Comparing strings checks
+                // their lengths first, so this is cheap when they differ.
+                let unchanged = running == state.running
+                    && update.status == state.status
+                    && update.image.is_none()
+                    && state.image.is_none()
+                    && update.command == state.output_command
+                    && update.output == state.output
+                    && update.stdout == state.stdout;
+                if unchanged {
+                    continue;
                 }
-                terminal.draw(|f| draw_ui(f, &state.cursor, &state.input, &state.output))?;
+                state.running = running;
+                state.output.clone_from(&update.output);
+                state.stdout.clone_from(&update.stdout);
+                transform_output(&mut state, &config);
+                if update.command != state.output_command {
+                    state.changed.clear();
+                }
+                state.output_command.clone_from(&update.command);
+                state.status = update.status;
+                state.image.clone_from(&update.image);
+                if update.done {
+                    highlight_changes(&mut state, &config);
+                }
+                drop(update);
+                draw(terminal, &mut state, &config)?;
+            },
+            Ok(()) = direct_output_rx.changed() => {
+                let update = direct_output_rx.borrow_and_update();
+                state.direct_output.clone_from(&update.output);
+                state.direct_status = update.status;
+                drop(update);
+                draw(terminal, &mut state, &config)?;
+            },
+            Some(result) = sub_rx.recv() => {
+                match result {
+                    Ok(output) if output.status.success() => {
+                        let stdout = String::from_utf8_lossy(&output.stdout);
+                        state.editor().insert_str(&shell::quote(stdout.trim_end_matches('\n')));
+                        state.message = None;
+                        input_changed(&handlers, &mut state, &config).await?;
+                    },
+                    Ok(output) => state.message = Some(format!("Sub-command failed: {}", failure(&output))),
+                    Err(e) => state.message = Some(format!("Failed to run sub-command: {e}")),
+                }
+                draw(terminal, &mut state, &config)?;
+            },
+            Some((input, result)) = formatted_rx.recv() => {
+                // The input changed since, and a newer result is on its way.
+                if input != state.input.text() {
+                    continue;
+                }
+                state.formatted = match result {
+                    Ok(output) if output.status.success() => {
+                        Some(Ok(String::from_utf8_lossy(&output.stdout).trim_end().to_string()))
+                    },
+                    Ok(output) => Some(Err(failure(&output))),
+                    Err(e) => Some(Err(format!("Failed to run formatter: {e}"))),
+                };
+                draw(terminal, &mut state, &config)?;
             },
+            Some(result) = hook_rx.recv() => {
+                match result {
+                    Ok(output) if output.status.success() => continue,
+                    Ok(output) => state.message = Some(format!("on-start hook failed: {}", failure(&output))),
+                    Err(e) => state.message = Some(format!("Failed to run on-start hook: {e}")),
+                }
+                draw(terminal, &mut state, &config)?;
+            },
+            // The key log would give secrets away just as well.
+            maybe_action = input_handler(&mut event_stream, &mut verbatim, keylog.as_ref().filter(|_| !state.sensitive)) => {
+                // Take it off the channel to avoid deadlocking.
+                let Some(action) = maybe_action else { continue };
+
+                if let Action::Focus(focused) = action {
+                    state.unfocused = !focused;
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+                // Keys only come from a terminal with focus, whatever it
+                // reported before.
+                state.unfocused = false;
+
+                if let Mode::Prompt(prompt) = &mut state.mode {
+                    match action {
+                        Action::Abort => state.mode = Mode::Normal,
+                        Action::Done => {
+                            let input = prompt.input.text().to_string();
+                            match prompt.kind {
+                                PromptKind::SubCommand => {
+                                    state.message = Some(format!("Running {input}"));
+                                    let sub_tx = sub_tx.clone();
+                                    let config = config.clone();
+                                    tokio::spawn(async move {
+                                        let _ = sub_tx.send(child::output(&input, &config).await).await;
+                                    });
+                                }
+                                PromptKind::Percentage => match input.trim() {
+                                    "g" => state.current_line = 0,
+                                    // Clamped to the output when drawing.
+                                    "G" => state.current_line = usize::MAX,
+                                    input => match input.trim_end_matches('%').parse::<usize>() {
+                                        Ok(percent) => {
+                                            let lines = state.shown_output().lines().count();
+                                            state.current_line = lines.saturating_sub(1) * percent.min(100) / 100;
+                                        },
+                                        Err(_) => state.message = Some(format!("Not a percentage: {input:?}")),
+                                    },
+                                },
+                                PromptKind::PlaylistIndex => if let Some(playlist) = &mut state.playlist {
+                                    let index = input.trim().parse::<usize>().ok().and_then(|i| i.checked_sub(1));
+                                    if index.is_some_and(|i| playlist.go(i)) {
+                                        state.input = Editor::new(playlist.current());
+                                        state.message = None;
+                                        input_changed(&handlers, &mut state, &config).await?;
+                                    } else {
+                                        state.message = Some(format!("No command {input:?}, there are {}", playlist.len()));
+                                    }
+                                },
+                            }
+                            state.mode = Mode::Normal;
+                        },
+                        Action::CursorLeft => {
+                            prompt.input.left();
+                        },
+                        Action::CursorRight => {
+                            prompt.input.right();
+                        },
+                        Action::Delete => {
+                            prompt.input.delete();
+                        },
+                        Action::Type(chr) => prompt.input.insert(chr),
+                        _ => {},
+                    }
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+
+                if let Mode::Completion(picker) = &mut state.mode {
+                    match action {
+                        Action::Abort => state.mode = Mode::Normal,
+                        Action::Up => picker.up(),
+                        Action::Down => picker.down(),
+                        Action::Done => {
+                            if let Some(candidate) = picker.selected().cloned() {
+                                complete(&mut state.input, &candidate);
+                                input_changed(&handlers, &mut state, &config).await?;
+                            }
+                            state.mode = Mode::Normal;
+                        },
+                        _ => {},
+                    }
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+
+                if let Mode::Samples(picker) = &mut state.mode {
+                    match action {
+                        Action::Abort => state.mode = Mode::Normal,
+                        Action::Up => picker.up(),
+                        Action::Down => picker.down(),
+                        Action::Done => {
+                            // Command samples are inserted like sub-commands.
+                            match picker.selected().cloned() {
+                                Some(Sample::Command { command, .. }) => {
+                                    state.message = Some(format!("Running {command}"));
+                                    let sub_tx = sub_tx.clone();
+                                    let config = config.clone();
+                                    tokio::spawn(async move {
+                                        let _ = sub_tx.send(child::output(&command, &config).await).await;
+                                    });
+                                },
+                                Some(sample) => match sample.generate() {
+                                    Ok(value) => {
+                                        state.editor().insert_str(&shell::quote(&value));
+                                        input_changed(&handlers, &mut state, &config).await?;
+                                    },
+                                    Err(e) => state.message = Some(format!("Failed to make a sample value: {e}")),
+                                },
+                                None => {},
+                            }
+                            state.mode = Mode::Normal;
+                        },
+                        _ => {},
+                    }
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+
+                if let Mode::SelectLine = state.mode {
+                    match action {
+                        Action::Abort | Action::SelectLine => state.mode = Mode::Normal,
+                        Action::Up => state.current_line = state.current_line.saturating_sub(1),
+                        // Clamped to the output when drawing.
+                        Action::Down => state.current_line += 1,
+                        Action::Done => {
+                            if let Some(line) = state.shown_output().lines().nth(state.current_line) {
+                                state.input = Editor::new(load_line(line, &config));
+                                input_changed(&handlers, &mut state, &config).await?;
+                            }
+                            state.mode = Mode::Normal;
+                        },
+                        _ => {},
+                    }
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+
+                if let Mode::References(selected) = &mut state.mode {
+                    match action {
+                        Action::Abort | Action::References => {
+                            state.mode = Mode::Normal;
+                            state.references.clear();
+                        },
+                        Action::Up => *selected = selected.saturating_sub(1),
+                        Action::Down => *selected = (*selected + 1).min(state.references.len() - 1),
+                        Action::Done => {
+                            let reference = &state.references[*selected];
+                            let status = suspend(terminal, &config, &mut references::edit_command(reference))?;
+                            if !status.success() {
+                                state.message = Some(format!("The editor failed: {status}"));
+                            }
+                        },
+                        _ => {},
+                    }
+                    if let Mode::References(selected) = state.mode {
+                        state.current_line = state.references[selected].output_line;
+                    }
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+
+                if let Mode::FilePicker(picker) = &mut state.mode {
+                    match action {
+                        Action::Abort => state.mode = Mode::Normal,
+                        Action::Up => picker.up(),
+                        Action::Down => picker.down(),
+                        // Directories that can't be read are skipped.
+                        Action::Done => if let Ok(Some(path)) = picker.select() {
+                            state.editor().insert_str(&shell::quote(&path.to_string_lossy()));
+                            state.mode = Mode::Normal;
+                            input_changed(&handlers, &mut state, &config).await?;
+                        },
+                        _ => {},
+                    }
+                    draw(terminal, &mut state, &config)?;
+                    continue;
+                }
+
+                match action {
+                    Action::Done => match on_enter(config.empty_enter, &state.command()) {
+                        Some(accepted) => return Ok(exit(&handlers, state, accepted).await),
+                        None => state.message = Some("Nothing to accept, Esc to exit".to_string()),
+                    },
+                    Action::Abort => return Ok(exit(&handlers, state, false).await),
+                    Action::CursorLeft => {
+                        state.editor().left();
+                    },
+                    Action::CursorRight => {
+                        state.editor().right();
+                    },
+                    Action::Delete => if state.editor().delete() {
+                        input_changed(&handlers, &mut state, &config).await?;
+                    },
+                    Action::Type(chr) => {
+                        state.editor().insert(chr);
+                        input_changed(&handlers, &mut state, &config).await?;
+                    },
+                    Action::InsertOutput => state.mode = Mode::Prompt(Prompt::new(PromptKind::SubCommand)),
+                    Action::InsertSample => {
+                        let items = config.samples.iter().map(|s| (s.label(), s.clone())).collect();
+                        state.mode = Mode::Samples(Picker::new("Samples", items));
+                    },
+                    Action::PickFile => if let Ok(picker) = FilePicker::new() {
+                        state.mode = Mode::FilePicker(picker);
+                    },
+                    Action::Up => state.current_line = state.current_line.saturating_sub(1),
+                    // Clamped to the output when drawing.
+                    Action::Down => state.current_line += 1,
+                    Action::Page { down, divisor } => {
+                        let page = config.page_size.unwrap_or(state.output_area.height as usize);
+                        let lines = (page / divisor).max(1);
+                        state.current_line = if down {
+                            state.current_line.saturating_add(lines)
+                        } else {
+                            state.current_line.saturating_sub(lines)
+                        };
+                    },
+                    Action::Top => state.current_line = 0,
+                    Action::Bottom => state.current_line = usize::MAX,
+                    Action::Run => run(&handlers, &mut state, &config).await?,
+                    Action::ToggleLineNumbers => state.line_numbers = state.line_numbers.next(),
+                    Action::ShowLine => state.show_line = !state.show_line,
+                    Action::ToggleSensitive => state.sensitive = !state.sensitive,
+                    // A command with a mistake in it can't be formatted, so
+                    // the same key fixes it.
+                    Action::AcceptFormatted if state.mistake.as_ref().is_some_and(|m| m.fixed.is_some()) => if state.template.is_none() {
+                        if let Some(fixed) = state.mistake.take().and_then(|m| m.fixed) {
+                            state.input = Editor::new(fixed);
+                            input_changed(&handlers, &mut state, &config).await?;
+                        }
+                    },
+                    Action::AcceptFormatted => if let Some(Ok(formatted)) = state.formatted.take() {
+                        if state.template.is_none() && formatted != state.input.text() {
+                            state.input = Editor::new(formatted);
+                            input_changed(&handlers, &mut state, &config).await?;
+                        }
+                    },
+                    Action::ToggleDryRun => if config.dry_run {
+                        state.dry_run_off = !state.dry_run_off;
+                        input_changed(&handlers, &mut state, &config).await?;
+                    },
+                    Action::ToggleSplit => {
+                        state.split = !state.split;
+                        if state.split {
+                            input_changed(&handlers, &mut state, &config).await?;
+                        }
+                    },
+                    Action::Tab => match &mut state.template {
+                        Some(template) => template.next_field(),
+                        None => {
+                            let mut candidates = word_candidates(&state.input);
+                            if candidates.len() == 1 {
+                                complete(&mut state.input, &candidates.remove(0));
+                                input_changed(&handlers, &mut state, &config).await?;
+                            } else if candidates.len() > 1 {
+                                let items = candidates.into_iter().map(|c| (c.label.clone(), c)).collect();
+                                state.mode = Mode::Completion(Picker::new("Completions", items));
+                            }
+                        },
+                    },
+                    Action::PreviousField => if let Some(template) = &mut state.template {
+                        template.previous_field();
+                    },
+                    Action::HistoryPrevious | Action::HistoryNext if state.playlist.is_some() => {
+                        let playlist = state.playlist.as_mut().expect("checked above");
+                        let moved = if action == Action::HistoryPrevious {
+                            playlist.previous()
+                        } else {
+                            playlist.next()
+                        };
+                        if moved {
+                            state.input = Editor::new(playlist.current());
+                            input_changed(&handlers, &mut state, &config).await?;
+                        }
+                    },
+                    Action::Tidy => if state.template.is_none() {
+                        match shell::tidy(state.input.text()) {
+                            Ok(tidy) if tidy != state.input.text() => {
+                                let before = std::mem::replace(&mut state.input, Editor::new(tidy));
+                                input_changed(&handlers, &mut state, &config).await?;
+                                state.undo = Some(before);
+                            },
+                            Ok(_) => {},
+                            Err(e) => state.message = Some(format!("Can't tidy the command: {e}")),
+                        }
+                    },
+                    // Going back puts the last piped command back in the
+                    // input, with whatever was piped into it.
+                    Action::Pipe if state.command().trim().is_empty() => if let Some(piped) = state.piped.pop() {
+                        state.template = None;
+                        state.input = Editor::new(piped.command);
+                        input_changed(&handlers, &mut state, &config).await?;
+                    },
+                    Action::Pipe => {
+                        if state.running != Running::No || state.output_command != state.live_command() {
+                            state.message = Some("Wait for the command to finish before piping its output".to_string());
+                        } else if state.image.is_some() {
+                            state.message = Some("Images can't be piped".to_string());
+                        } else if let Some(stdout) = &state.stdout {
+                            // Only stdout goes down a pipe, so error messages
+                            // don't, and the next command gets nothing.
+                            state.piped.push(Piped {
+                                command: state.command(),
+                                stdout: stdout.as_str().into(),
+                            });
+                            // The next command is typed from scratch.
+                            state.template = None;
+                            state.input = Editor::default();
+                            input_changed(&handlers, &mut state, &config).await?;
+                        } else {
+                            state.message = Some("Only complete text output can be piped".to_string());
+                        }
+                    },
+                    Action::Undo => if let Some(before) = state.undo.take() {
+                        state.input = before;
+                        input_changed(&handlers, &mut state, &config).await?;
+                    },
+                    // Templates can't take a whole command.
+                    Action::SelectLine => if state.template.is_none() {
+                        state.mode = Mode::SelectLine;
+                    },
+                    Action::References => {
+                        state.references = references::find(state.shown_output(), &config.reference_pattern);
+                        match state.references.first() {
+                            Some(reference) => {
+                                state.current_line = reference.output_line;
+                                state.mode = Mode::References(0);
+                            },
+                            None => state.message = Some("No file references in the output".to_string()),
+                        }
+                    },
+                    Action::ToggleTimestamps => {
+                        state.timestamps = !state.timestamps;
+                        transform_output(&mut state, &config);
+                    },
+                    Action::Manual => {
+                        match manual_page(&state) {
+                            Ok(page) => {
+                                let status = suspend(terminal, &config, std::process::Command::new("man").arg(&page))?;
+                                if !status.success() {
+                                    state.message = Some(format!("man {page} failed: {status}"));
+                                }
+                            },
+                            Err(message) => state.message = Some(message),
+                        }
+                    },
+                    Action::GoTo => {
+                        let kind = if state.playlist.is_some() {
+                            PromptKind::PlaylistIndex
+                        } else {
+                            PromptKind::Percentage
+                        };
+                        state.mode = Mode::Prompt(Prompt::new(kind));
+                    },
+                    // History entries are whole commands, which don't fit
+                    // into a template.
+                    Action::HistoryPrevious | Action::HistoryNext if state.template.is_none() => {
+                        let entry = if action == Action::HistoryPrevious {
+                            state.history.previous(state.input.text())
+                        } else {
+                            state.history.next()
+                        };
+                        if let Some(entry) = entry {
+                            state.input = Editor::new(entry);
+                            input_changed(&handlers, &mut state, &config).await?;
+                        }
+                    },
+                    Action::HistoryPrevious | Action::HistoryNext => {},
+                    // Handled before the modes.
+                    Action::Focus(_) => {},
+                }
+                draw(terminal, &mut state, &config)?;
+            },
+        }
+    }
+}
+
+/// The command to load for a selected output line, which is the line itself
+/// unless there is a template for it.
+fn load_line(line: &str, config: &Config) -> String {
+    let line = output::strip_ansi(line);
+    match &config.select_template {
+        Some(template) => template.replace("{line}", &shell::quote(&line)),
+        None => line,
+    }
+}
+
+/// Highlights the output lines that changed since the command last finished,
+/// once it finished again.
+fn highlight_changes(state: &mut State, config: &Config) {
+    let shown = state.shown_output().to_string();
+    let previous = state
+        .finished
+        .replace((state.output_command.clone(), shown));
+    let (Some(duration), Some((command, old))) = (config.highlight_changes, previous) else {
+        return;
+    };
+    let new = &state.finished.as_ref().expect("set above").1;
+    if command != state.output_command || old == *new {
+        return;
+    }
+
+    // Lines in common keep their highlight, if they have one, moving along
+    // with the end of the output.
+    let (start, end) = output::common_lines(&old, new);
+    let old_len = old.lines().count();
+    let new_len = new.lines().count();
+    let deadline = Instant::now() + duration;
+    let previous = std::mem::take(&mut state.changed);
+    let kept = |i: usize| previous.get(i).copied().flatten();
+    state.changed = (0..new_len)
+        .map(|i| {
+            if i < start {
+                kept(i)
+            } else if i >= new_len - end {
+                kept(i + old_len - new_len)
+            } else {
+                Some(deadline)
+            }
+        })
+        .collect();
+}
+
+/// Updates the output as shown after the output or how to show it changed.
+fn transform_output(state: &mut State, config: &Config) {
+    state.control_only = output::is_mostly_control(&state.output);
+    // The nulls stay in the accepted output, for things like `xargs -0`.
+    state.transformed = config
+        .null_delimited
+        .then(|| state.output.replace('\0', "\n"));
+    if state.timestamps {
+        let output = state.shown_output();
+        let rewritten = timestamps::rewrite(output, config.timestamp_format, SystemTime::now());
+        state.transformed = Some(rewritten);
+    }
+
+    // The references have to keep up with the output while moving through
+    // them, and there is nothing to move through once they are gone.
+    if let Mode::References(selected) = &mut state.mode {
+        let output = state.transformed.as_deref().unwrap_or(&state.output);
+        state.references = references::find(output, &config.reference_pattern);
+        if state.references.is_empty() {
+            state.mode = Mode::Normal;
+        } else {
+            *selected = (*selected).min(state.references.len() - 1);
+            state.current_line = state.references[*selected].output_line;
+        }
+    }
+}
+
+/// The manual page for the command under the cursor, or why there is none.
+fn manual_page(state: &State) -> Result<String, String> {
+    // Template fields don't have commands in them, so go by the whole one.
+    let (command, offset) = match &state.template {
+        Some(template) => (template.command(), 0),
+        None => (
+            state.input.text().to_string(),
+            state.input.before_cursor().len(),
+        ),
+    };
+    let tokens = shell::tokenize(&command).map_err(|e| format!("Can't find the command: {e}"))?;
+    let name = shell::command_at(&tokens, offset)
+        .and_then(|tokens| shell::words(tokens).next())
+        .ok_or("No command to show the manual of")?;
+
+    // Checking first keeps man's error from flashing by before the
+    // interface is back.
+    let found = std::process::Command::new("man")
+        .arg("-w")
+        .arg(name)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| format!("Failed to run man: {e}"))?;
+    if !found.success() {
+        return Err(format!("No manual entry for {name}"));
+    }
+    Ok(name.to_string())
+}
+
+/// The word before the cursor, which is everything back to the last
+/// whitespace.
+fn current_word(input: &Editor) -> &str {
+    let before = input.before_cursor();
+    let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    &before[start..]
+}
+
+/// The ways to complete the word before the cursor. Quoted words aren't
+/// completed.
+fn word_candidates(input: &Editor) -> Vec<Candidate> {
+    let word = current_word(input);
+    if word.contains(['\'', '"', '\\']) {
+        return vec![];
+    }
+    let before = &input.before_cursor()[..input.before_cursor().len() - word.len()];
+    complete::candidates(word, complete::is_command(before))
+}
+
+/// Replaces the word before the cursor with a completion.
+fn complete(input: &mut Editor, candidate: &Candidate) {
+    for _ in 0..current_word(input).chars().count() {
+        input.delete();
+    }
+    input.insert_str(&shell::quote(&candidate.word));
+}
+
+/// Why a command failed, which is the first line of its stderr if it printed
+/// anything.
+fn failure(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr
+        .lines()
+        .next()
+        .map_or_else(|| output.status.to_string(), String::from)
+}
+
+/// Where to send commands to run.
+struct Handlers {
+    shell: Sender<Cmd>,
+    /// Runs commands without a shell, for the split view.
+    direct: Sender<Cmd>,
+    /// Gets the results of formatting the input.
+    formatted: Sender<(String, io::Result<Output>)>,
+}
+
+/// Lets the user know that the child handlers stopped, which they only do
+/// on their own if something went wrong.
+///
+/// The last output stays, and Enter and Esc still exit as usual. A panic is
+/// passed on, as the panic hook has already restored the terminal.
+fn child_exited(state: &mut State, result: Result<Result<()>, JoinError>) -> Result<()> {
+    let reason = match result {
+        Err(e) if e.is_panic() => return Err(e).context("child handler panicked"),
+        Err(e) => e.to_string(),
+        Ok(Ok(())) => "the child handler exited".to_string(),
+        Ok(Err(e)) => format!("{e:#}"),
+    };
+    state.running = Running::No;
+    state.message = Some(format!("Commands can't run anymore: {reason}"));
+    Ok(())
+}
+
+/// Whether Enter accepts the output, aborts, or does nothing if `None`, which
+/// only differ for an empty command.
+fn on_enter(empty_enter: EmptyEnter, command: &str) -> Option<bool> {
+    if !command.trim().is_empty() {
+        return Some(true);
+    }
+    match empty_enter {
+        EmptyEnter::Ignore => None,
+        EmptyEnter::Abort => Some(false),
+        EmptyEnter::Accept => Some(true),
+    }
+}
+
+/// Stops running commands, returning the command and output to exit with.
+async fn exit(handlers: &Handlers, state: State, accepted: bool) -> Exit {
+    shut_down(handlers).await;
+    Exit {
+        accepted,
+        command: state.pipeline(),
+        output: state.output,
+        sensitive: state.sensitive,
+        history: state.history,
+    }
+}
+
+/// Tells the child handlers to stop running commands.
+async fn shut_down(handlers: &Handlers) {
+    // This only fails if a child handler has stopped already, in which case
+    // there is nothing left to do.
+    let _ = handlers.shell.send(Cmd::Done).await;
+    let _ = handlers.direct.send(Cmd::Done).await;
+}
+
+/// Runs the command after the input changed, unless it has to be run by
+/// hand.
+async fn input_changed(handlers: &Handlers, state: &mut State, config: &Config) -> Result<()> {
+    // Undoing only goes back to before the last tidy, and only until the
+    // next change.
+    state.undo = None;
+
+    // Templates can't take a formatted command.
+    if let (Some(formatter), None) = (&config.formatter, &state.template) {
+        let formatter = formatter.clone();
+        let input = state.input.text().to_string();
+        let formatted_tx = handlers.formatted.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            let result = child::format(&formatter, &input, &config).await;
+            let _ = formatted_tx.send((input, result)).await;
+        });
+    }
+
+    state.mistake = shell::find_mistake(&state.command());
+
+    state.dry_run = None;
+    state.dry_run_cancelled = None;
+    if config.dry_run && !state.dry_run_off {
+        match dry_run::inject(&state.command(), &config.dry_run_flags) {
+            Ok(dry_run) => state.dry_run = dry_run,
+            Err(cancelled) => state.dry_run_cancelled = Some(cancelled.flag),
+        }
+    }
+
+    if config.safe_preview {
+        let safety = safety::classify(&state.command(), config);
+        state.safety = Some(safety);
+        if safety != Safety::ReadOnly {
+            return Ok(());
         }
     }
+    // Running it anyway would do it for real.
+    if state.dry_run_cancelled.is_some() {
+        return Ok(());
+    }
+    run(handlers, state, config).await
+}
+
+/// Runs the command, showing the running indicator if it takes a while.
+async fn run(handlers: &Handlers, state: &mut State, config: &Config) -> Result<()> {
+    // This only fails if the child handlers stopped, which the user has
+    // been told about.
+    let sent = handlers
+        .shell
+        .send(Cmd::Input(state.live_command(), state.stdin()))
+        .await;
+    if sent.is_err() {
+        return Ok(());
+    }
+    if state.split {
+        let _ = handlers
+            .direct
+            .send(Cmd::Input(state.live_command(), state.stdin()))
+            .await;
+    }
+    if state.running == Running::No {
+        state.running = Running::Quietly(Instant::now() + config.indicator_delay);
+    }
+    Ok(())
 }
 
 #[derive(Eq, PartialEq, Clone, Copy)]
@@ -113,12 +1223,74 @@ enum Action {
     Abort,
     CursorLeft,
     CursorRight,
+    Up,
+    Down,
+    /// Moves through the output by a page, or a part of one.
+    Page {
+        down: bool,
+        divisor: usize,
+    },
+    Top,
+    Bottom,
     Delete,
     Type(char),
+    PickFile,
+    InsertOutput,
+    InsertSample,
+    Run,
+    ToggleLineNumbers,
+    ShowLine,
+    ToggleSensitive,
+    ToggleSplit,
+    ToggleDryRun,
+    AcceptFormatted,
+    /// Moves to the next template field, or completes the word before the
+    /// cursor without a template.
+    Tab,
+    PreviousField,
+    HistoryPrevious,
+    HistoryNext,
+    GoTo,
+    Manual,
+    ToggleTimestamps,
+    SelectLine,
+    References,
+    Tidy,
+    Undo,
+    /// Pipes the output into the next command, or goes back to the last
+    /// piped one without a command.
+    Pipe,
+    /// The terminal gained or lost focus.
+    Focus(bool),
 }
 
-async fn input_handler(events: &mut EventStream) -> Option<Action> {
-    match events.next().await {
+/// Turns key presses into actions.
+///
+/// After Ctrl+V the next key is inserted as the character it stands for,
+/// like readline's quoted insert, so that Tab or control characters can be
+/// typed.
+async fn input_handler(
+    events: &mut EventStream,
+    verbatim: &mut bool,
+    keylog: Option<&KeyLog>,
+) -> Option<Action> {
+    let event = events.next().await;
+    if let (Some(keylog), Some(Ok(Event::Key(key)))) = (keylog, &event) {
+        keylog.record(key);
+    }
+    if *verbatim {
+        return match event {
+            Some(Ok(Event::Key(key))) if key.kind == event::KeyEventKind::Press => {
+                *verbatim = false;
+                verbatim_char(key).map(Action::Type)
+            }
+            _ => None,
+        };
+    }
+
+    match event {
+        Some(Ok(Event::FocusGained)) => Some(Action::Focus(true)),
+        Some(Ok(Event::FocusLost)) => Some(Action::Focus(false)),
         Some(Ok(Event::Key(event::KeyEvent {
             code: KeyCode::Esc,
             kind: event::KeyEventKind::Press,
@@ -139,11 +1311,187 @@ async fn input_handler(events: &mut EventStream) -> Option<Action> {
             kind: event::KeyEventKind::Press,
             ..
         }))) => Some(Action::CursorRight),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: code @ (KeyCode::Up | KeyCode::Down),
+            modifiers: modifiers @ (KeyModifiers::SHIFT | KeyModifiers::ALT),
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Page {
+            down: code == KeyCode::Down,
+            divisor: if modifiers == KeyModifiers::SHIFT {
+                2
+            } else {
+                4
+            },
+        }),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: code @ (KeyCode::PageUp | KeyCode::PageDown),
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Page {
+            down: code == KeyCode::PageDown,
+            divisor: 1,
+        }),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Home,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Top),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::End,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Bottom),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Up,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Up),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Down,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Down),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Tab,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Tab),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::BackTab,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::PreviousField),
         Some(Ok(Event::Key(event::KeyEvent {
             code: KeyCode::Backspace,
             kind: event::KeyEventKind::Press,
             ..
         }))) => Some(Action::Delete),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('t'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::PickFile),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('x'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::InsertOutput),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('y'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::InsertSample),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('p'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::HistoryPrevious),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('n'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::HistoryNext),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('f'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::AcceptFormatted),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('s'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::ToggleSplit),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('d'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::ToggleDryRun),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::F(3),
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Tidy),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::F(4),
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Pipe),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('z'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Undo),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::F(2),
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::ToggleTimestamps),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::F(1),
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Manual),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('o'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::SelectLine),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('e'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::References),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('g'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::GoTo),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('r'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::Run),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('l'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::ToggleLineNumbers),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('w'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::ShowLine),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('k'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => Some(Action::ToggleSensitive),
+        Some(Ok(Event::Key(event::KeyEvent {
+            code: KeyCode::Char('v'),
+            modifiers: KeyModifiers::CONTROL,
+            kind: event::KeyEventKind::Press,
+            ..
+        }))) => {
+            *verbatim = true;
+            None
+        }
         Some(Ok(Event::Key(event::KeyEvent {
             code: KeyCode::Char(char),
             kind: event::KeyEventKind::Press,
@@ -153,88 +1501,632 @@ async fn input_handler(events: &mut EventStream) -> Option<Action> {
     }
 }
 
-#[derive(Debug)]
-enum Cmd {
-    Input(String),
-    Done,
+/// The character a key stands for when inserted verbatim.
+fn verbatim_char(key: event::KeyEvent) -> Option<char> {
+    match key.code {
+        // Ctrl+A to Ctrl+_ are the control characters 0x01 to 0x1f.
+        KeyCode::Char(c @ ('@'..='_' | 'a'..='z'))
+            if key.modifiers.contains(KeyModifiers::CONTROL) =>
+        {
+            Some((c.to_ascii_uppercase() as u8 - b'@') as char)
+        }
+        KeyCode::Char(c) => Some(c),
+        KeyCode::Tab => Some('\t'),
+        KeyCode::Enter => Some('\n'),
+        KeyCode::Esc => Some('\x1b'),
+        KeyCode::Backspace => Some('\x7f'),
+        _ => None,
+    }
 }
 
-async fn child_handler(
-    mut cmd_chan: Receiver<Cmd>,
-    output_chan: Sender<Option<String>>,
-) -> Result<()> {
-    let mut child_proc: Option<process::Child> = None;
+/// The output lines in view, scrolled to follow the current line.
+fn output_lines<'a>(state: &'a mut State, config: &Config, height: usize) -> Vec<Spans<'a>> {
+    // Shown as text, these would mess up the pane.
+    if state.control_only {
+        let message = "The output is terminal control sequences, which can't be shown as text";
+        return vec![Spans::from(Span::styled(
+            message,
+            Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+        ))];
+    }
 
-    loop {
-        select! {
-            Some(Ok(output)) = OptionFuture::from(child_proc.map(|c| c.wait_with_output())) => {
-                if !output.stdout.is_empty() {
-                    if let Ok(s) = String::from_utf8(output.stdout) {
-                        output_chan.send(Some(s)).await?;
-                    } else {
-                        output_chan.send(Some("Non-UTF8 stdout".to_string())).await?;
-                    }
-                } else if !output.stderr.is_empty() {
-                    if let Ok(s) = String::from_utf8(output.stderr) {
-                        output_chan.send(Some(s)).await?;
-                    } else {
-                        output_chan.send(Some("Non-UTF8 stderr".to_string())).await?;
-                    }
-                } else {
-                    output_chan.send(None).await?;
-                }
-                child_proc = None;
-            },
-            msg = cmd_chan.recv() => {
-                if let Some(cmd) = msg {
-                    match cmd {
-                        Cmd::Input(input) => {
-                            let proc = process::Command::new("zsh")
-                                .arg("-c")
-                                .arg(&input)
-                                .stdin(std::process::Stdio::piped())
-                                .stdout(std::process::Stdio::piped())
-                                .stderr(std::process::Stdio::piped())
-                                .kill_on_drop(true)
-                                .spawn();
-                            if let Ok(p) = proc {
-                                child_proc = Some(p);
-                            } else {
-                                child_proc = None;
-                            }
-                        },
-                        Cmd::Done => return Ok(()),
-                    }
-                } else {
-                    child_proc = None;
-                }
-            },
+    let output = state.transformed.as_deref().unwrap_or(&state.output);
+    let lines: Vec<_> = output.lines().collect();
+    let max_scroll = lines.len().saturating_sub(height);
+    state.current_line = state.current_line.min(lines.len().saturating_sub(1));
+
+    // Lines are cut off at the edge of the pane unless told otherwise.
+    let max_width = config
+        .max_line_width
+        .unwrap_or(state.output_area.width as usize);
+
+    let selecting = matches!(state.mode, Mode::SelectLine);
+    let selected_reference = match state.mode {
+        Mode::References(selected) => Some(selected),
+        _ => None,
+    };
+    if state.line_numbers == LineNumbers::Off && !selecting && selected_reference.is_none() {
+        // Without a gutter or selection there is no visible current line, so
+        // moving it just scrolls.
+        state.scroll = state.current_line.min(max_scroll);
+        state.current_line = state.scroll;
+        return lines
+            .into_iter()
+            .skip(state.scroll)
+            .take(height)
+            .enumerate()
+            .map(|(i, line)| {
+                let style = changed_style(state, config, state.scroll + i);
+                Spans::from(truncate(vec![Span::styled(line, style)], max_width, config))
+            })
+            .collect();
+    }
+
+    if state.current_line < state.scroll {
+        state.scroll = state.current_line;
+    } else if state.current_line >= state.scroll + height {
+        state.scroll = state.current_line + 1 - height;
+    }
+
+    let width = lines.len().to_string().len().max(3);
+    let max_width = match (state.line_numbers, config.max_line_width) {
+        // The gutter takes up part of the pane.
+        (LineNumbers::Absolute | LineNumbers::Relative, None) => {
+            max_width.saturating_sub(width + 1)
         }
+        _ => max_width,
+    };
+    lines
+        .into_iter()
+        .enumerate()
+        .skip(state.scroll)
+        .take(height)
+        .map(|(i, line)| {
+            let line_style = if selecting && i == state.current_line {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                changed_style(state, config, i)
+            };
+            let content = match selected_reference {
+                Some(selected) => reference_spans(line, i, &state.references, selected),
+                None => vec![Span::styled(line, line_style)],
+            };
+            let mut content = truncate(content, max_width, config);
+            if state.line_numbers == LineNumbers::Off {
+                return Spans::from(content);
+            }
+
+            let (number, style) = if i == state.current_line {
+                (i + 1, Style::default().fg(Color::Yellow))
+            } else if state.line_numbers == LineNumbers::Relative {
+                (
+                    i.abs_diff(state.current_line),
+                    Style::default().fg(Color::DarkGray),
+                )
+            } else {
+                (i + 1, Style::default().fg(Color::DarkGray))
+            };
+            content.insert(0, Span::styled(format!("{number:>width$} "), style));
+            Spans::from(content)
+        })
+        .collect()
+}
+
+/// How wide the line number gutter is, with room for the numbers of all
+/// output lines and a space.
+fn gutter_width(state: &State) -> usize {
+    if state.line_numbers == LineNumbers::Off {
+        return 0;
     }
+    state
+        .shown_output()
+        .lines()
+        .count()
+        .to_string()
+        .len()
+        .max(3)
+        + 1
 }
 
-fn draw_ui(
-    f: &mut Frame<CrosstermBackend<std::io::Stdout>>,
-    cursor: &u16,
-    input: &str,
-    output: &str,
-) {
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .margin(1)
-        .constraints([Constraint::Length(3), Constraint::Min(3)].as_ref())
-        .split(f.size());
+/// How to show an output line, which is highlighted if it changed recently.
+fn changed_style(state: &State, config: &Config, line: usize) -> Style {
+    match state.changed.get(line) {
+        Some(Some(_)) => Style::default().bg(config.highlight_color).fg(Color::Black),
+        _ => Style::default(),
+    }
+}
 
+/// Cuts a line off after `max` columns, ending it in the ellipsis.
+fn truncate<'a>(spans: Vec<Span<'a>>, max: usize, config: &Config) -> Vec<Span<'a>> {
+    if spans.iter().map(|s| s.content.width()).sum::<usize>() <= max {
+        return spans;
+    }
+
+    let mut room = max.saturating_sub(config.ellipsis.width());
+    let mut truncated = vec![];
+    for span in spans {
+        let width = span.content.width();
+        if width <= room {
+            room -= width;
+            truncated.push(span);
+            continue;
+        }
+        // Stop before the first character that doesn't fit, so that wide
+        // ones aren't cut in half.
+        let mut end = 0;
+        for (i, c) in span.content.char_indices() {
+            let width = c.width().unwrap_or(0);
+            if width > room {
+                break;
+            }
+            room -= width;
+            end = i + c.len_utf8();
+        }
+        let content = match span.content {
+            Cow::Borrowed(s) => Cow::Borrowed(&s[..end]),
+            Cow::Owned(s) => Cow::Owned(s[..end].to_string()),
+        };
+        truncated.push(Span::styled(content, span.style));
+        truncated.push(Span::styled(config.ellipsis.clone(), span.style));
+        break;
+    }
+    truncated
+}
+
+/// An output line with the file references in it underlined, and the
+/// selected one highlighted.
+fn reference_spans<'a>(
+    line: &'a str,
+    output_line: usize,
+    references: &[Reference],
+    selected: usize,
+) -> Vec<Span<'a>> {
+    let mut spans = vec![];
+    let mut end = 0;
+    for (i, reference) in references
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.output_line == output_line)
+    {
+        let mut style = Style::default()
+            .fg(Color::Cyan)
+            .add_modifier(Modifier::UNDERLINED);
+        if i == selected {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        spans.push(Span::raw(&line[end..reference.span.start]));
+        spans.push(Span::styled(&line[reference.span.clone()], style));
+        end = reference.span.end;
+    }
+    spans.push(Span::raw(&line[end..]));
+    spans
+}
+
+/// How a command exited, to go in a pane title.
+fn exit_label(status: Option<ExitStatus>) -> String {
+    match status.map(|s| s.code()) {
+        Some(Some(code)) => format!(" (exit {code})"),
+        Some(None) => " (killed)".to_string(),
+        None => String::new(),
+    }
+}
+
+fn status_line(state: &State, config: &Config) -> Spans<'static> {
+    let mut spans = vec![];
+    if let Some(playlist) = &state.playlist {
+        spans.push(Span::raw(format!("{} ", playlist.label())));
+    }
+    // Make sure it's clear where commands run.
+    if let Some(container) = &config.container {
+        spans.push(Span::styled(
+            format!("[{}:{}] ", container.engine, container.name),
+            Style::default().fg(Color::Cyan),
+        ));
+    } else if let Some(host) = &config.ssh {
+        spans.push(Span::styled(
+            format!("[ssh:{host}] "),
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    if let Some(dry_run) = &state.dry_run {
+        spans.push(Span::styled(
+            format!("[dry run: {}]", dry_run.flags.join(" ")),
+            Style::default().fg(Color::Magenta),
+        ));
+        spans.push(Span::raw(" Ctrl+D to turn off "));
+    } else if let Some(flag) = &state.dry_run_cancelled {
+        spans.push(Span::styled(
+            format!("[dry run cancelled by {flag}]"),
+            Style::default().fg(Color::Magenta),
+        ));
+        spans.push(Span::raw(" Ctrl+R to run "));
+    } else if config.dry_run && state.dry_run_off {
+        spans.push(Span::styled(
+            "[dry run off] ",
+            Style::default().fg(Color::Magenta),
+        ));
+    }
+    // The prefix isn't in the input, so show what really runs.
+    if config.dry_run && config.prefix.is_some() && !state.sensitive {
+        let command = child::with_prefix(&state.live_command(), config).into_owned();
+        spans.push(Span::styled(
+            format!("$ {command} "),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    if let Some(piped) = state.piped.last() {
+        let lines = piped.stdout.lines().count();
+        let plural = if lines == 1 { "" } else { "s" };
+        // The piped commands are as secret as this one.
+        let from = if state.sensitive {
+            String::new()
+        } else {
+            format!(" from {}", piped.command)
+        };
+        spans.push(Span::styled(
+            format!("[stdin: {lines} line{plural}{from}]"),
+            Style::default().fg(Color::Cyan),
+        ));
+        spans.push(Span::raw(" F4 without a command to go back "));
+    }
+    if state.sensitive {
+        spans.push(Span::styled(
+            "[hidden] ",
+            Style::default().fg(Color::Magenta),
+        ));
+    }
+    if let Some(safety) = state.safety {
+        let color = match safety {
+            Safety::ReadOnly => Color::Green,
+            Safety::Mutating => Color::Red,
+            Safety::Unknown => Color::Yellow,
+        };
+        spans.push(Span::styled(
+            format!("[{safety}]"),
+            Style::default().fg(color),
+        ));
+        if safety != Safety::ReadOnly {
+            spans.push(Span::raw(" Ctrl+R to run "));
+        }
+    }
+    if let Some(mistake) = &state.mistake {
+        spans.push(Span::styled(
+            mistake.message.clone(),
+            Style::default().fg(Color::Yellow),
+        ));
+        // Fixes only apply to the whole input.
+        if mistake.fixed.is_some() && state.template.is_none() {
+            spans.push(Span::raw(", Ctrl+F to fix"));
+        }
+        spans.push(Span::raw(" "));
+    }
+    if let Mode::References(selected) = state.mode {
+        let reference = &state.references[selected];
+        let column = reference.column.map_or(String::new(), |c| format!(":{c}"));
+        spans.push(Span::raw(format!(
+            "{}:{}{column} ({}/{}) Enter to open ",
+            reference.path,
+            reference.line,
+            selected + 1,
+            state.references.len(),
+        )));
+    }
+    if let Some(message) = &state.message {
+        spans.push(Span::raw(message.clone()));
+    }
+    Spans::from(spans)
+}
+
+type Term = Terminal<CrosstermBackend<io::Stdout>>;
+
+/// Draws the interface, and the image the command printed over the output if
+/// there is one.
+fn draw(terminal: &mut Term, state: &mut State, config: &Config) -> Result<()> {
+    // Whatever changes in the meantime is drawn once focus is back.
+    if state.unfocused {
+        return Ok(());
+    }
+    terminal.draw(|f| draw_ui(f, state, config))?;
+    let Some(protocol) = config.graphics else {
+        return Ok(());
+    };
+
+    // Leave the first line for the description of the image.
+    let area = state.output_area;
+    let area = Rect::new(
+        area.x,
+        area.y + 1,
+        area.width,
+        area.height.saturating_sub(1),
+    );
+    let escape = match (&state.image, &state.mode) {
+        (Some(image), Mode::Normal) if area.area() > 0 => protocol.show(image, area),
+        _ => None,
+    };
+
+    if state.image_shown {
+        match protocol.delete() {
+            Some(delete) => write!(terminal.backend_mut(), "{delete}")?,
+            // Only a full redraw gets rid of the image if nothing replaces it.
+            None if escape.is_none() => {
+                terminal.clear()?;
+                terminal.draw(|f| draw_ui(f, state, config))?;
+            }
+            None => {}
+        }
+    }
+    state.image_shown = escape.is_some();
+    if let Some(escape) = escape {
+        let backend = terminal.backend_mut();
+        queue!(backend, SavePosition, MoveTo(area.x, area.y))?;
+        write!(backend, "{escape}")?;
+        queue!(backend, RestorePosition)?;
+    }
+    terminal.backend_mut().flush()?;
+    Ok(())
+}
+
+fn draw_ui(f: &mut Frame<CrosstermBackend<std::io::Stdout>>, state: &mut State, config: &Config) {
+    // The formatted command goes in a second line of the input box.
+    let formatted = state
+        .formatted
+        .as_ref()
+        .filter(|_| state.template.is_none() && !state.sensitive);
+    let input_lines = if formatted.is_some() { 2 } else { 1 };
+    // The compact layout has no borders or margin, and the status line gets a
+    // row of its own.
+    let compact = config.compact;
+    let chunks = if compact {
+        Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(input_lines),
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(f.size())
+    } else {
+        Layout::default()
+            .direction(Direction::Vertical)
+            .margin(1)
+            .constraints([Constraint::Length(input_lines + 2), Constraint::Min(3)].as_ref())
+            .split(f.size())
+    };
+    let block = |title: String, border_style: Style| {
+        if compact {
+            Block::default()
+        } else {
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_style(border_style)
+        }
+    };
+
+    // The output is from an older command, like while the current one hasn't
+    // printed anything yet or with --safe-preview before it is run by hand.
+    let stale = state.output_command != state.live_command();
+
+    let (input, cursor_column) = match &state.template {
+        Some(template) => template.render(state.sensitive),
+        None if state.sensitive => (
+            vec![Span::raw(state.input.masked())],
+            state.input.masked_cursor_column(),
+        ),
+        None => (
+            vec![Span::raw(state.input.display())],
+            state.input.cursor_column(),
+        ),
+    };
+    let (mut input, cursor_column) = if compact {
+        let prompt = if stale {
+            Span::styled("● ", Style::default().fg(Color::Yellow))
+        } else {
+            Span::styled("$ ", Style::default().fg(Color::DarkGray))
+        };
+        let input = std::iter::once(prompt).chain(input).collect::<Vec<_>>();
+        (vec![Spans::from(input)], cursor_column + 2)
+    } else {
+        (vec![Spans::from(input)], cursor_column)
+    };
+    if let Some(formatted) = formatted {
+        // Newlines would break the line, so show them as a symbol.
+        let (text, style) = match formatted {
+            Ok(text) => (
+                text.replace('\n', " ⏎ "),
+                Style::default().fg(Color::DarkGray),
+            ),
+            Err(e) => (e.clone(), Style::default().fg(Color::Red)),
+        };
+        input.push(Spans::from(Span::styled(text, style)));
+    }
     // TODO Add dynamic resize for longer inputs.
     let input_box = Paragraph::new(input)
-        .block(Block::default().title("Stdin").borders(Borders::ALL))
+        .block(block("Stdin".to_string(), Style::default()))
         .wrap(Wrap { trim: false });
     f.render_widget(input_box, chunks[0]);
 
-    // TODO Add vertical scrolling.
-    let output_box =
-        Paragraph::new(output).block(Block::default().title("Stdout").borders(Borders::ALL));
-    f.render_widget(output_box, chunks[1]);
+    let running = state.running == Running::Visibly;
+    let (output_chunk, mut title) = if state.split {
+        let halves = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(chunks[1]);
 
-    f.set_cursor(2 + cursor, 2);
+        let title = format!("Direct exec{}", exit_label(state.direct_status));
+        let direct_block = block(title, Style::default());
+        let direct_area = direct_block.inner(halves[1]);
+        f.render_widget(direct_block, halves[1]);
+        // Scrolls along with the shell output.
+        let lines: Vec<_> = state
+            .direct_output
+            .lines()
+            .skip(state.scroll)
+            .take(direct_area.height as usize)
+            .map(Spans::from)
+            .collect();
+        f.render_widget(Paragraph::new(lines), direct_area);
+
+        let title = if running {
+            "Shell (running)".to_string()
+        } else {
+            format!("Shell{}", exit_label(state.status))
+        };
+        (halves[0], title)
+    } else if running {
+        (chunks[1], "Stdout (running)".to_string())
+    } else {
+        (chunks[1], "Stdout".to_string())
+    };
+    let mut border_style = Style::default();
+    if stale {
+        title += " ●";
+        border_style = border_style.fg(Color::Yellow);
+    }
+    let output_block = block(title, border_style);
+    let output_area = output_block.inner(output_chunk);
+    state.output_area = output_area;
+    let text_width = (output_area.width as usize).saturating_sub(gutter_width(state));
+    config
+        .pane_width
+        .store(text_width as u16, atomic::Ordering::Relaxed);
+    f.render_widget(output_block, output_chunk);
+    f.render_widget(
+        Paragraph::new(output_lines(state, config, output_area.height as usize)),
+        output_area,
+    );
+
+    // The whole current line goes in a popup at the bottom of the output,
+    // wrapped to fit.
+    let current_line = state.shown_output().lines().nth(state.current_line);
+    if let Some(line) = current_line.filter(|_| state.show_line) {
+        let inner_width = output_chunk.width.saturating_sub(2).max(1) as usize;
+        let rows = line.width() / inner_width + 1;
+        let height = (rows as u16 + 2).min(output_chunk.height);
+        let area = Rect::new(
+            output_chunk.x,
+            output_chunk.bottom() - height,
+            output_chunk.width,
+            height,
+        );
+        let popup = Paragraph::new(line.to_string())
+            .block(
+                Block::default()
+                    .title(format!("Line {}", state.current_line + 1))
+                    .borders(Borders::ALL),
+            )
+            .wrap(Wrap { trim: false });
+        f.render_widget(Clear, area);
+        f.render_widget(popup, area);
+    }
+
+    // The status line sits in the bottom margin, or in its own row.
+    let size = f.size();
+    if compact {
+        let mut status = status_line(state, config);
+        // There is no title to show this in.
+        if running {
+            status.0.insert(0, Span::raw("[running] "));
+        }
+        f.render_widget(Paragraph::new(status), chunks[2]);
+    } else if size.height > 0 {
+        let status_area = Rect::new(2, size.height - 1, size.width.saturating_sub(4), 1);
+        f.render_widget(Paragraph::new(status_line(state, config)), status_area);
+    }
+
+    if let Mode::FilePicker(picker) = &mut state.mode {
+        picker.render(f, chunks[1]);
+    } else if let Mode::Completion(picker) = &mut state.mode {
+        picker.render(f, chunks[1]);
+    } else if let Mode::Samples(picker) = &mut state.mode {
+        picker.render(f, chunks[1]);
+    } else if let Mode::Prompt(prompt) = &state.mode {
+        prompt.render(f, chunks[1]);
+    } else {
+        let input_area = block(String::new(), Style::default()).inner(chunks[0]);
+        f.set_cursor(input_area.x + cursor_column, input_area.y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Handlers whose child handler already stopped, like after an error.
+    async fn stopped_handlers(state: &mut State) -> Handlers {
+        let (shell, shell_rx) = channel(1);
+        let (direct, _) = channel(1);
+        let (formatted, _) = channel(1);
+        // Nothing gets the output, so the handler fails on the first update.
+        let (output_tx, _) = watch::channel(Update::default());
+        let config = Arc::new(Config::default());
+        let task = tokio::spawn(child_handler(shell_rx, output_tx, config, Exec::Shell));
+        shell
+            .send(Cmd::Input("true".to_string(), None))
+            .await
+            .unwrap();
+        child_exited(state, task.await).unwrap();
+        Handlers {
+            shell,
+            direct,
+            formatted,
+        }
+    }
+
+    #[test]
+    fn truncates_to_width() {
+        let config = Config::default();
+        let text = |spans: Vec<Span>| {
+            spans
+                .iter()
+                .map(|s| s.content.to_string())
+                .collect::<String>()
+        };
+        assert_eq!(
+            text(truncate(vec![Span::raw("abcdef")], 4, &config)),
+            "abc…"
+        );
+        assert_eq!(text(truncate(vec![Span::raw("abcd")], 4, &config)), "abcd");
+        // Wide characters aren't cut in half.
+        assert_eq!(text(truncate(vec![Span::raw("日本語")], 4, &config)), "日…");
+    }
+
+    #[test]
+    fn enter_on_empty_command() {
+        for command in ["", "  \t"] {
+            assert_eq!(on_enter(EmptyEnter::Ignore, command), None);
+            assert_eq!(on_enter(EmptyEnter::Abort, command), Some(false));
+            assert_eq!(on_enter(EmptyEnter::Accept, command), Some(true));
+        }
+        for empty_enter in [EmptyEnter::Ignore, EmptyEnter::Abort, EmptyEnter::Accept] {
+            assert_eq!(on_enter(empty_enter, "ls"), Some(true));
+        }
+    }
+
+    #[tokio::test]
+    async fn quits_cleanly_after_child_handler_stopped() {
+        for accepted in [true, false] {
+            let mut state = State {
+                input: Editor::new("echo hi"),
+                ..State::default()
+            };
+            let handlers = stopped_handlers(&mut state).await;
+            assert!(state.message.is_some());
+
+            // Running more commands doesn't fail either.
+            run(&handlers, &mut state, &Config::default())
+                .await
+                .unwrap();
+            assert!(state.running == Running::No);
+
+            let exit = exit(&handlers, state, accepted).await;
+            assert_eq!(exit.accepted, accepted);
+            assert_eq!(exit.command, "echo hi");
+        }
+    }
 }
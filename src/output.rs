@@ -0,0 +1,330 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+/// Output of a command as a terminal would show it, built up as it streams
+/// in.
+///
+/// Carriage returns move back to the start of the current line, so progress
+/// bars that redraw themselves only leave their latest state behind.
+///
+/// Records can also be separated by null bytes, like `find -print0` does,
+/// in which case newlines and carriage returns are part of the records.
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    /// The start of a UTF-8 sequence that is split across chunks.
+    pending: Vec<u8>,
+    invalid: bool,
+    /// Finished lines, each including its newline.
+    lines: String,
+    /// The line still being written.
+    line: Vec<char>,
+    column: usize,
+    limit: Option<Limit>,
+    /// Whether some output was dropped to stay within the limit.
+    truncated: bool,
+    /// What ends a line, a newline or a null byte.
+    separator: char,
+}
+
+/// How much output to keep, and which.
+#[derive(Debug, Clone)]
+pub struct Limit {
+    pub bytes: usize,
+    pub truncate: Truncate,
+    /// Shown where output was dropped.
+    pub marker: String,
+}
+
+/// Which end of the output to drop once it is too long.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Truncate {
+    /// Drop the start to keep the latest output, for logs and the like.
+    #[default]
+    Head,
+    /// Drop the end to keep the earliest output.
+    Tail,
+}
+
+impl FromStr for Truncate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "head" => Ok(Self::Head),
+            "tail" => Ok(Self::Tail),
+            _ => bail!("expected one of head, tail"),
+        }
+    }
+}
+
+impl OutputBuffer {
+    pub fn new(limit: Option<Limit>, null_delimited: bool) -> Self {
+        Self {
+            limit,
+            separator: if null_delimited { '\0' } else { '\n' },
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        // Once the end is being dropped, there is nothing left to do.
+        let full = self.truncated && matches!(&self.limit, Some(l) if l.truncate == Truncate::Tail);
+        if self.invalid || full {
+            return;
+        }
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            // The last sequence isn't complete yet.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => {
+                self.invalid = true;
+                return;
+            }
+        };
+        let rest = self.pending.split_off(valid);
+        let pending = std::mem::replace(&mut self.pending, rest);
+        for c in String::from_utf8(pending).expect("validated above").chars() {
+            self.put(c);
+        }
+        self.bound();
+    }
+
+    /// Marks the end of the output, after which a split sequence is invalid.
+    pub fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            self.invalid = true;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.line.is_empty() && self.pending.is_empty()
+    }
+
+    /// Whether some output was dropped to stay within the limit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The output so far, unless it isn't valid UTF-8.
+    pub fn text(&self) -> Option<String> {
+        if self.invalid {
+            return None;
+        }
+        let mut text = self.lines.clone();
+        text.extend(&self.line);
+        match &self.limit {
+            Some(limit) if self.truncated => match limit.truncate {
+                Truncate::Head => Some(format!("{}{}{text}", limit.marker, self.separator)),
+                Truncate::Tail => {
+                    if !text.is_empty() && !text.ends_with(self.separator) {
+                        text.push(self.separator);
+                    }
+                    Some(text + &limit.marker)
+                }
+            },
+            _ => Some(text),
+        }
+    }
+
+    /// Drops output beyond the limit, at line boundaries where possible.
+    fn bound(&mut self) {
+        let Some(limit) = &self.limit else {
+            return;
+        };
+        let separator = self.separator as u8;
+        // Counting the characters of the current line as bytes is close
+        // enough.
+        if self.lines.len() + self.line.len() <= limit.bytes {
+            return;
+        }
+        self.truncated = true;
+
+        match limit.truncate {
+            Truncate::Head => {
+                // Drop down to three quarters of the limit, so that this
+                // doesn't move the whole buffer for every chunk.
+                let keep = limit.bytes / 4 * 3;
+                if self.line.len() > keep {
+                    let excess = self.line.len() - keep;
+                    self.line.drain(..excess);
+                    self.column = self.column.saturating_sub(excess);
+                    self.lines.clear();
+                    return;
+                }
+                let excess = (self.lines.len() + self.line.len()).saturating_sub(keep);
+                let end = self.lines.as_bytes()[excess..]
+                    .iter()
+                    .position(|&b| b == separator)
+                    .map_or(self.lines.len(), |i| excess + i + 1);
+                self.lines.drain(..end);
+            }
+            Truncate::Tail => {
+                if self.lines.len() > limit.bytes {
+                    // Cut after the last whole line, or inside the first one
+                    // if that is already too long.
+                    let end = self.lines.as_bytes()[..limit.bytes]
+                        .iter()
+                        .rposition(|&b| b == separator)
+                        .map(|i| i + 1)
+                        .or_else(|| {
+                            (0..=limit.bytes)
+                                .rev()
+                                .find(|&i| self.lines.is_char_boundary(i))
+                        })
+                        .unwrap_or(0);
+                    self.lines.truncate(end);
+                }
+                self.line.clear();
+                self.column = 0;
+            }
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        match c {
+            c if c == self.separator => {
+                self.lines.extend(self.line.drain(..));
+                self.lines.push(c);
+                self.column = 0;
+            }
+            '\r' if self.separator == '\n' => self.column = 0,
+            c => {
+                if let Some(old) = self.line.get_mut(self.column) {
+                    *old = c;
+                } else {
+                    self.line.push(c);
+                }
+                self.column += 1;
+            }
+        }
+    }
+}
+
+/// How many lines two outputs have in common at the start and at the end,
+/// which leaves the lines in between as the ones that changed.
+pub fn common_lines(old: &str, new: &str) -> (usize, usize) {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    let start = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    // The end can't overlap with the start.
+    let end = old[start..]
+        .iter()
+        .rev()
+        .zip(new[start..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (start, end)
+}
+
+/// Removes ANSI escape sequences like colours and cursor movements.
+pub fn strip_ansi(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates up to a final byte in @..~.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: terminated by BEL or ST (ESC \).
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Everything else is a two-character sequence.
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// Whether output is mostly terminal control sequences, like from `clear` or
+/// an interactive program, which can't be shown as text.
+pub fn is_mostly_control(s: &str) -> bool {
+    if !s.contains('\x1b') {
+        return false;
+    }
+    let visible = strip_ansi(s)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .count();
+    visible * 10 < s.len()
+}
+
+/// Wraps a command and its output in a fenced markdown code block.
+pub fn markdown(command: &str, output: &str) -> String {
+    let output = strip_ansi(output);
+    // The fence needs to be longer than any run of backticks inside it.
+    let longest_run = output
+        .split(|c| c != '`')
+        .chain(command.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+
+    let mut block = format!("{fence}shell\n$ {command}\n{output}");
+    if !block.ends_with('\n') {
+        block.push('\n');
+    }
+    block.push_str(&fence);
+    block.push('\n');
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes the bytes in chunks split at the given offsets.
+    fn push_split(buffer: &mut OutputBuffer, bytes: &[u8], splits: &[usize]) {
+        let mut start = 0;
+        for &end in splits.iter().chain([&bytes.len()]) {
+            buffer.push(&bytes[start..end]);
+            start = end;
+        }
+        buffer.finish();
+    }
+
+    #[test]
+    fn progress_keeps_final_state() {
+        let mut buffer = OutputBuffer::new(None, false);
+        push_split(&mut buffer, b"10%\r50%\r100%\n", &[2, 5, 9]);
+        assert_eq!(buffer.text().as_deref(), Some("100%\n"));
+    }
+
+    #[test]
+    fn progress_with_split_utf8() {
+        let bytes = "é 10%\ré 50%\ré 100%\ndone\n".as_bytes();
+        // Split inside the first é, which is two bytes.
+        let mut buffer = OutputBuffer::new(None, false);
+        push_split(&mut buffer, bytes, &[1, 7, 8, 15]);
+        assert_eq!(buffer.text().as_deref(), Some("é 100%\ndone\n"));
+    }
+
+    #[test]
+    fn unfinished_utf8_is_invalid() {
+        let mut buffer = OutputBuffer::new(None, false);
+        push_split(&mut buffer, &"é".as_bytes()[..1], &[]);
+        assert_eq!(buffer.text(), None);
+    }
+}
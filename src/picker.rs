@@ -0,0 +1,158 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Modifier, Style},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState},
+    Frame,
+};
+
+/// A popup list to choose one of several items from.
+#[derive(Debug)]
+pub struct Picker<T> {
+    title: String,
+    items: Vec<(String, T)>,
+    state: ListState,
+}
+
+impl<T> Picker<T> {
+    /// Creates a picker showing each item with its label.
+    pub fn new(title: impl Into<String>, items: Vec<(String, T)>) -> Self {
+        let mut state = ListState::default();
+        if !items.is_empty() {
+            state.select(Some(0));
+        }
+        Self {
+            title: title.into(),
+            items,
+            state,
+        }
+    }
+
+    pub fn up(&mut self) {
+        if let Some(i) = self.state.selected() {
+            self.state.select(Some(i.saturating_sub(1)));
+        }
+    }
+
+    pub fn down(&mut self) {
+        if let Some(i) = self.state.selected() {
+            self.state.select(Some((i + 1).min(self.items.len() - 1)));
+        }
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        self.state.selected().map(|i| &self.items[i].1)
+    }
+
+    /// Draws the picker over whatever is in `area`.
+    pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let items: Vec<_> = self
+            .items
+            .iter()
+            .map(|(label, _)| ListItem::new(label.as_str()))
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title(self.title.as_str())
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_widget(Clear, area);
+        f.render_stateful_widget(list, area, &mut self.state);
+    }
+}
+
+/// A picker to browse the file system, starting at the working directory.
+#[derive(Debug)]
+pub struct FilePicker {
+    /// The directory being listed, relative to the working directory.
+    dir: PathBuf,
+    picker: Picker<Entry>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Parent,
+    Dir(String),
+    File(String),
+}
+
+impl FilePicker {
+    pub fn new() -> io::Result<Self> {
+        Self::open(PathBuf::new())
+    }
+
+    fn open(dir: PathBuf) -> io::Result<Self> {
+        let read_dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &dir
+        };
+
+        let mut dirs = vec![];
+        let mut files = vec![];
+        for entry in fs::read_dir(read_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Follow symlinks so links to directories can be entered.
+            if entry.path().is_dir() {
+                dirs.push(name);
+            } else {
+                files.push(name);
+            }
+        }
+        dirs.sort();
+        files.sort();
+
+        let items = std::iter::once(("../".to_string(), Entry::Parent))
+            .chain(dirs.into_iter().map(|d| (format!("{d}/"), Entry::Dir(d))))
+            .chain(files.into_iter().map(|f| (f.clone(), Entry::File(f))))
+            .collect();
+        let title = format!("Files in {}", read_dir.display());
+
+        Ok(Self {
+            picker: Picker::new(title, items),
+            dir,
+        })
+    }
+
+    pub fn up(&mut self) {
+        self.picker.up()
+    }
+
+    pub fn down(&mut self) {
+        self.picker.down()
+    }
+
+    /// Enters the selected directory, or returns the path of the selected
+    /// file.
+    pub fn select(&mut self) -> io::Result<Option<PathBuf>> {
+        let dir = match self.picker.selected() {
+            Some(Entry::File(name)) => return Ok(Some(self.dir.join(name))),
+            Some(Entry::Dir(name)) => self.dir.join(name),
+            Some(Entry::Parent) => {
+                let mut dir = self.dir.clone();
+                match dir.components().next_back() {
+                    Some(Component::Normal(_)) => {
+                        dir.pop();
+                    }
+                    _ => dir.push(".."),
+                }
+                dir
+            }
+            None => return Ok(None),
+        };
+        *self = Self::open(dir)?;
+        Ok(None)
+    }
+
+    pub fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        self.picker.render(f, area)
+    }
+}
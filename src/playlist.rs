@@ -0,0 +1,63 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+/// Commands from a file to step through, one per line.
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    commands: Vec<String>,
+    position: usize,
+}
+
+impl Playlist {
+    /// Reads the commands from a file, skipping blank lines.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read playlist {}", path.display()))?;
+        let commands: Vec<_> = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(String::from)
+            .collect();
+        if commands.is_empty() {
+            bail!("playlist {} has no commands", path.display());
+        }
+        Ok(Self {
+            commands,
+            position: 0,
+        })
+    }
+
+    pub fn current(&self) -> &str {
+        &self.commands[self.position]
+    }
+
+    /// Moves to the next command, returning whether there is one.
+    pub fn next(&mut self) -> bool {
+        self.go(self.position + 1)
+    }
+
+    /// Moves to the previous command, returning whether there is one.
+    pub fn previous(&mut self) -> bool {
+        self.position > 0 && self.go(self.position - 1)
+    }
+
+    /// Moves to the command at an index, counting from 0, returning whether
+    /// there is one.
+    pub fn go(&mut self, index: usize) -> bool {
+        if index >= self.commands.len() {
+            return false;
+        }
+        self.position = index;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Where in the playlist the current command is, like `3/12`.
+    pub fn label(&self) -> String {
+        format!("{}/{}", self.position + 1, self.commands.len())
+    }
+}
@@ -0,0 +1,53 @@
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+use crate::editor::Editor;
+
+/// A popup asking for a line of input.
+#[derive(Debug)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: Editor,
+}
+
+/// What the input of a prompt is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    /// A command whose output gets inserted into the command line.
+    SubCommand,
+    /// The number of a command in the playlist to go to.
+    PlaylistIndex,
+    /// How far through the output to go, in percent.
+    Percentage,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind) -> Self {
+        Self {
+            kind,
+            input: Editor::default(),
+        }
+    }
+
+    /// Draws the prompt at the top of `area` and places the cursor in it.
+    pub fn render<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let title = match self.kind {
+            PromptKind::SubCommand => "Insert output of",
+            PromptKind::PlaylistIndex => "Go to command number",
+            PromptKind::Percentage => "Go to % of the output, g for the top, G for the bottom",
+        };
+        let area = Rect {
+            height: area.height.min(3),
+            ..area
+        };
+        let prompt = Paragraph::new(self.input.display())
+            .block(Block::default().title(title).borders(Borders::ALL));
+        f.render_widget(Clear, area);
+        f.render_widget(prompt, area);
+        f.set_cursor(area.x + 1 + self.input.cursor_column(), area.y + 1);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{ops::Range, path::Path, process::Command};
+
+use regex::Regex;
+
+/// The pattern for references like `src/main.rs:12:5` in compiler or grep
+/// output, used unless `--reference-pattern` is given.
+pub const DEFAULT_PATTERN: &str =
+    r"(?P<path>[^\s:'\x22()\[\]]+):(?P<line>\d+)(?::(?P<column>\d+))?";
+
+/// A place in a file that the output refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub path: String,
+    pub line: usize,
+    pub column: Option<usize>,
+    /// The output line the reference is on.
+    pub output_line: usize,
+    /// Where the reference is in that line, in bytes.
+    pub span: Range<usize>,
+}
+
+/// Finds the references to existing files in the output, in order.
+///
+/// The pattern needs `path` and `line` groups, and can have a `column` one.
+/// Matches of paths that don't exist are left out, which gets rid of things
+/// like times and URLs that look similar.
+pub fn find(output: &str, pattern: &Regex) -> Vec<Reference> {
+    let mut references = vec![];
+    for (output_line, line) in output.lines().enumerate() {
+        for captures in pattern.captures_iter(line) {
+            let (Some(path), Some(number)) = (captures.name("path"), captures.name("line")) else {
+                continue;
+            };
+            let Ok(number) = number.as_str().parse() else {
+                continue;
+            };
+            if !Path::new(path.as_str()).is_file() {
+                continue;
+            }
+            let whole = captures.get(0).expect("there is always a whole match");
+            references.push(Reference {
+                path: path.as_str().to_string(),
+                line: number,
+                column: captures
+                    .name("column")
+                    .and_then(|c| c.as_str().parse().ok()),
+                output_line,
+                span: whole.range(),
+            });
+        }
+    }
+    references
+}
+
+/// The command to open `$VISUAL` or `$EDITOR` at a reference, falling back
+/// to vi.
+///
+/// Most editors, including vi, Emacs and nano, take the line as `+N`. The
+/// editor runs through the shell, since it is often set to something with
+/// arguments like `code --wait`.
+pub fn edit_command(reference: &Reference) -> Command {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg("editor")
+        .arg(format!("+{}", reference.line))
+        .arg(&reference.path);
+    command
+}
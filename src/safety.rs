@@ -0,0 +1,362 @@
+use std::fmt;
+
+use crate::{config::Config, shell};
+
+/// Commands that only read by default, used with `--safe-preview`.
+pub const READ_ONLY_COMMANDS: &[&str] = &[
+    "basename",
+    "cat",
+    "column",
+    "cut",
+    "date",
+    "df",
+    "diff",
+    "dirname",
+    "du",
+    "echo",
+    "fd",
+    "file",
+    "find",
+    "git blame",
+    "git branch",
+    "git diff",
+    "git log",
+    "git show",
+    "git status",
+    "grep",
+    "head",
+    "hostname",
+    "id",
+    "jq",
+    "less",
+    "ls",
+    "nl",
+    "printf",
+    "ps",
+    "pwd",
+    "realpath",
+    "rg",
+    "seq",
+    "sort",
+    "stat",
+    "tail",
+    "tr",
+    "tree",
+    "uname",
+    "uniq",
+    "wc",
+    "which",
+    "whoami",
+];
+
+/// Flags that make otherwise read-only commands write files or run other
+/// commands. Short ones also count as part of a group like `-ro`.
+const WRITING_FLAGS: &[(&str, &[&str])] = &[
+    ("date", &["-s", "--set"]),
+    ("fd", &["-x", "--exec", "-X", "--exec-batch"]),
+    ("hostname", &["-F", "--file", "-b", "--boot"]),
+    ("find", &["-delete", "-exec", "-ok", "-fprint", "-fls"]),
+    (
+        "git branch",
+        &[
+            "-d",
+            "-D",
+            "--delete",
+            "-m",
+            "-M",
+            "--move",
+            "-c",
+            "-C",
+            "--copy",
+            "-u",
+            "-f",
+            "--force",
+            "--set-upstream-to",
+            "--unset-upstream",
+            "--edit-description",
+        ],
+    ),
+    ("git diff", &["--output"]),
+    ("git log", &["--output"]),
+    ("git show", &["--output"]),
+    ("rg", &["--pre"]),
+    ("sort", &["-o", "--output"]),
+    ("tree", &["-o"]),
+];
+
+/// Commands that change things when given an argument, like `git branch
+/// name` or `hostname name`, along with the flags that make them only list
+/// things and the flags that take the next word as their value.
+const SETTING_COMMANDS: &[(&str, &[&str], &[&str])] = &[
+    (
+        "date",
+        &[],
+        &["-d", "--date", "-f", "--file", "-r", "--reference"],
+    ),
+    (
+        "git branch",
+        &[
+            "--list",
+            "-l",
+            "-a",
+            "--all",
+            "-r",
+            "--remotes",
+            "--contains",
+            "--no-contains",
+            "--merged",
+            "--no-merged",
+            "-v",
+            "--verbose",
+            "--points-at",
+        ],
+        &["--sort", "--format"],
+    ),
+    ("hostname", &[], &[]),
+];
+
+/// Commands that can change things by default, used with `--safe-preview`.
+pub const MUTATING_COMMANDS: &[&str] = &[
+    "chmod",
+    "chown",
+    "cp",
+    "dd",
+    "git checkout",
+    "git clean",
+    "git commit",
+    "git merge",
+    "git pull",
+    "git push",
+    "git rebase",
+    "git reset",
+    "kill",
+    "killall",
+    "ln",
+    "mkdir",
+    "mkfs",
+    "mv",
+    "pkill",
+    "reboot",
+    "rm",
+    "rmdir",
+    "shutdown",
+    "sudo",
+    "tee",
+    "touch",
+    "truncate",
+];
+
+/// How likely a command is to change something when run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Safety {
+    /// Every command in it is known to only read.
+    ReadOnly,
+    /// Some command in it is known to change things, or it writes to a file.
+    Mutating,
+    /// Not known either way.
+    Unknown,
+}
+
+impl fmt::Display for Safety {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "read-only"),
+            Self::Mutating => write!(f, "mutating"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Guesses whether a command is safe to run on every keystroke, going by the
+/// names of the commands in it.
+pub fn classify(command: &str, config: &Config) -> Safety {
+    // If it doesn't parse it's impossible to know what it would do.
+    let Ok(pipelines) = shell::parse(command) else {
+        return Safety::Unknown;
+    };
+
+    let mut safety = Safety::ReadOnly;
+    for stage in pipelines.iter().flat_map(|p| &p.stages) {
+        // Substitutions are left as words by the parser, but run commands
+        // of their own.
+        let text = &command[stage.span.clone()];
+        let substitutes = ["$(", "`", "<(", ">("].iter().any(|s| text.contains(s));
+        let writes_file = stage.redirects.iter().any(|redirect| {
+            let Some(target) = redirect.target.as_deref() else {
+                return false;
+            };
+            // Duplicating a descriptor like `2>&1` or closing one with `>&-`
+            // doesn't touch files, but `>& file` writes both streams to it.
+            let copies = redirect.operator.ends_with('&')
+                && (target == "-"
+                    || (!target.is_empty() && target.bytes().all(|b| b.is_ascii_digit())));
+            redirect.operator.contains('>') && !copies && target != "/dev/null"
+        });
+        let words: Vec<_> = stage.words.iter().map(String::as_str).collect();
+        if writes_file
+            || matches(&words, &config.mutating_commands)
+            || writes_through_arguments(&words)
+        {
+            return Safety::Mutating;
+        }
+        if substitutes || (!words.is_empty() && !matches(&words, &config.read_only_commands)) {
+            safety = Safety::Unknown;
+        }
+    }
+    safety
+}
+
+/// Whether a command writes because of its arguments, like `find -delete`,
+/// `uniq in out` or `git branch name`.
+fn writes_through_arguments(words: &[&str]) -> bool {
+    // The second file is where uniq writes to.
+    if words.first() == Some(&"uniq") {
+        let files = words[1..]
+            .iter()
+            .filter(|w| !w.starts_with('-') || **w == "-");
+        return files.count() >= 2;
+    }
+    if SETTING_COMMANDS.iter().any(|setting| sets(words, setting)) {
+        return true;
+    }
+    WRITING_FLAGS.iter().any(|(command, flags)| {
+        let expected: Vec<_> = command.split_whitespace().collect();
+        words.starts_with(&expected)
+            && words[expected.len()..]
+                .iter()
+                .any(|word| flags.iter().any(|flag| is_flag(word, flag)))
+    })
+}
+
+/// Whether a command from [`SETTING_COMMANDS`] gets an argument to set
+/// something to, rather than only options.
+fn sets(words: &[&str], (command, listing, with_value): &(&str, &[&str], &[&str])) -> bool {
+    let expected: Vec<_> = command.split_whitespace().collect();
+    if !words.starts_with(&expected) {
+        return false;
+    }
+    let args = &words[expected.len()..];
+    if args
+        .iter()
+        .any(|word| listing.iter().any(|flag| is_flag(word, flag)))
+    {
+        return false;
+    }
+
+    let mut options = true;
+    let mut args = args.iter();
+    while let Some(&word) = args.next() {
+        if options && word == "--" {
+            options = false;
+        } else if options && word.starts_with('-') && word != "-" {
+            if with_value.contains(&word) {
+                args.next();
+            }
+        } else if !(*command == "date" && word.starts_with('+')) {
+            // Formats like `date +%s` only print, anything else is set.
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether `word` is `flag`, possibly with a value attached like in
+/// `--output=file` or `-ofile`, or grouped with other short flags.
+fn is_flag(word: &str, flag: &str) -> bool {
+    if word.starts_with(flag) {
+        return true;
+    }
+    match (flag.strip_prefix('-'), word.strip_prefix('-')) {
+        (Some(letter), Some(group)) if letter.len() == 1 && !group.starts_with('-') => {
+            group.contains(letter)
+        }
+        _ => false,
+    }
+}
+
+/// Whether the words start with any of the given commands, which can consist
+/// of several words like `git push`.
+fn matches(words: &[&str], commands: &[String]) -> bool {
+    commands.iter().any(|command| {
+        let expected: Vec<_> = command.split_whitespace().collect();
+        !expected.is_empty() && words.starts_with(&expected)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutions_are_unknown() {
+        let config = Config::default();
+        for command in [
+            "echo $(rm -rf ~/x)",
+            "echo `rm -rf ~/x`",
+            "X=$(rm x) ls",
+            "diff <(ls a) <(ls b)",
+        ] {
+            assert_ne!(classify(command, &config), Safety::ReadOnly, "{command}");
+        }
+    }
+
+    #[test]
+    fn writing_arguments_are_mutating() {
+        let config = Config::default();
+        for command in [
+            "find . -delete",
+            "find . -name '*.o' -exec rm {} +",
+            "find . -fprint out",
+            "git branch -D foo",
+            "git branch -m old new",
+            "sort -o file file",
+            "sort -ro file file",
+            "sort --output=file file",
+            "uniq in out",
+            "git branch feature",
+            "git branch -f feature HEAD~",
+            "date 010100002030",
+            "date -u 010100002030",
+            "hostname box",
+            "hostname -F file",
+            "ls >& out",
+            "echo x >&file",
+            "ls 2>&out",
+        ] {
+            assert_eq!(classify(command, &config), Safety::Mutating, "{command}");
+        }
+    }
+
+    #[test]
+    fn env_and_awk_are_unknown() {
+        let config = Config::default();
+        assert_eq!(classify("env rm -rf x", &config), Safety::Unknown);
+        assert_eq!(
+            classify("awk 'BEGIN { system(\"rm x\") }'", &config),
+            Safety::Unknown
+        );
+    }
+
+    #[test]
+    fn reading_is_read_only() {
+        let config = Config::default();
+        for command in [
+            "find . -name '*.rs'",
+            "git branch -a",
+            "git branch --contains HEAD",
+            "git branch --list 'feat*'",
+            "git branch -av",
+            "git branch --sort=-committerdate",
+            "date +%s",
+            "date -d tomorrow +%F",
+            "hostname",
+            "hostname -f",
+            "sort -rn file | uniq -c",
+            "uniq file",
+            "ls 2>&1 > /dev/null",
+            "ls 2>&1 >&-",
+        ] {
+            assert_eq!(classify(command, &config), Safety::ReadOnly, "{command}");
+        }
+    }
+}
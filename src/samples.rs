@@ -0,0 +1,122 @@
+use std::{
+    fmt::Write,
+    fs::File,
+    io::{self, Read},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::timestamps;
+
+/// A kind of throwaway value to insert into a command, for trying it out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sample {
+    Uuid,
+    /// Today's date, like `2023-01-31`.
+    Date,
+    /// Seconds since the epoch.
+    Timestamp,
+    /// A path in the temporary directory that doesn't exist yet.
+    TempPath,
+    /// A number from 1 to 100.
+    Number,
+    /// Whatever a command prints, set with `--sample`.
+    Command {
+        name: String,
+        command: String,
+    },
+}
+
+/// The samples there always are, before the ones from `--sample`.
+pub const BUILT_IN: &[Sample] = &[
+    Sample::Uuid,
+    Sample::Date,
+    Sample::Timestamp,
+    Sample::TempPath,
+    Sample::Number,
+];
+
+/// Parses a sample like `NAME=COMMAND`.
+impl FromStr for Sample {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, command) = s
+            .split_once('=')
+            .filter(|(name, command)| !name.is_empty() && !command.is_empty())
+            .ok_or_else(|| anyhow!("expected NAME=COMMAND"))?;
+        Ok(Self::Command {
+            name: name.to_string(),
+            command: command.to_string(),
+        })
+    }
+}
+
+impl Sample {
+    /// How to show the sample in the picker.
+    pub fn label(&self) -> String {
+        match self {
+            Self::Uuid => "UUID".to_string(),
+            Self::Date => "Today's date".to_string(),
+            Self::Timestamp => "Unix timestamp".to_string(),
+            Self::TempPath => "Temporary file path".to_string(),
+            Self::Number => "Number from 1 to 100".to_string(),
+            Self::Command { name, command } => format!("{name} ({command})"),
+        }
+    }
+
+    /// Makes up a value, for all but command samples, which have to be
+    /// run.
+    pub fn generate(&self) -> io::Result<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match self {
+            Self::Uuid => {
+                // A version 4 UUID, which is random apart from the version
+                // and variant bits.
+                let mut bytes: [u8; 16] = random()?;
+                bytes[6] = bytes[6] & 0x0f | 0x40;
+                bytes[8] = bytes[8] & 0x3f | 0x80;
+                let mut uuid = String::with_capacity(36);
+                for (i, byte) in bytes.iter().enumerate() {
+                    if [4, 6, 8, 10].contains(&i) {
+                        uuid.push('-');
+                    }
+                    write!(uuid, "{byte:02x}").expect("writing to a string");
+                }
+                Ok(uuid)
+            }
+            Self::Date => {
+                let tm = timestamps::local_time(now as i64)
+                    .ok_or_else(|| io::Error::other("no local time"))?;
+                Ok(format!(
+                    "{:04}-{:02}-{:02}",
+                    tm.tm_year + 1900,
+                    tm.tm_mon + 1,
+                    tm.tm_mday
+                ))
+            }
+            Self::Timestamp => Ok(now.to_string()),
+            Self::TempPath => {
+                let suffix = u32::from_ne_bytes(random()?);
+                let path = std::env::temp_dir().join(format!("live-preview-{suffix:08x}"));
+                Ok(path.to_string_lossy().into_owned())
+            }
+            Self::Number => Ok((u32::from_ne_bytes(random()?) % 100 + 1).to_string()),
+            Self::Command { .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "command samples have to be run",
+            )),
+        }
+    }
+}
+
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
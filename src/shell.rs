@@ -0,0 +1,450 @@
+use std::{borrow::Cow, fmt, iter::Peekable, ops::Range, str::CharIndices};
+
+/// Quotes a string so the shell reads it back as a single word.
+pub fn quote(s: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%^".contains(c);
+    if !s.is_empty() && s.chars().all(is_safe) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(format!("'{}'", s.replace('\'', r"'\''")))
+}
+
+/// A word or operator in a shell command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The token with quotes and escapes resolved.
+    pub value: String,
+    /// Where the token is in the command, in bytes.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Word,
+    /// Separates commands, like `|`, `&&` or `;`.
+    Separator,
+    /// Redirects a stream, like `>`, `2>>` or `<`.
+    Redirect,
+}
+
+/// Why a command couldn't be split into tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenizeError {
+    /// A quote at this byte offset is never closed.
+    UnmatchedSingleQuote(usize),
+    UnmatchedDoubleQuote(usize),
+    /// The command ends in a backslash with nothing to escape.
+    TrailingBackslash,
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnmatchedSingleQuote(_) => write!(f, "unmatched single quote"),
+            Self::UnmatchedDoubleQuote(_) => write!(f, "unmatched double quote"),
+            Self::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+impl std::error::Error for TokenizeError {}
+
+/// Splits a command into words and operators the way a POSIX shell would,
+/// without expanding anything.
+pub fn tokenize(command: &str) -> Result<Vec<Token>, TokenizeError> {
+    let mut tokens = vec![];
+    let mut chars = command.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c == '\n' || c == ';' || c == '|' || c == '&' {
+            chars.next();
+            let mut end = start + 1;
+            // `||`, `&&`, `;;` and `|&` are single operators.
+            if let Some(&(i, next)) = chars.peek() {
+                if next == c || (c == '|' && next == '&') {
+                    chars.next();
+                    end = i + 1;
+                } else if c == '&' && next == '>' {
+                    // `&>` and `&>>` redirect both streams.
+                    chars.next();
+                    end = i + 1;
+                    if let Some(&(i, '>')) = chars.peek() {
+                        chars.next();
+                        end = i + 1;
+                    }
+                    tokens.push(token(command, TokenKind::Redirect, start..end));
+                    continue;
+                }
+            }
+            tokens.push(token(command, TokenKind::Separator, start..end));
+        } else if c == '>' || c == '<' {
+            let end = redirect_end(&mut chars);
+            tokens.push(token(command, TokenKind::Redirect, start..end));
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            // Comments run to the end of the line.
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else {
+            let mut value = String::new();
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                match c {
+                    '\'' => {
+                        chars.next();
+                        loop {
+                            match chars.next() {
+                                Some((_, '\'')) => break,
+                                Some((_, c)) => value.push(c),
+                                None => return Err(TokenizeError::UnmatchedSingleQuote(i)),
+                            }
+                        }
+                    }
+                    '"' => {
+                        chars.next();
+                        loop {
+                            match chars.next() {
+                                Some((_, '"')) => break,
+                                // Only some characters can be escaped in
+                                // double quotes.
+                                Some((_, '\\')) => match chars.next() {
+                                    Some((_, c @ ('"' | '\\' | '$' | '`'))) => value.push(c),
+                                    Some((_, '\n')) => {}
+                                    Some((_, c)) => {
+                                        value.push('\\');
+                                        value.push(c);
+                                    }
+                                    None => return Err(TokenizeError::UnmatchedDoubleQuote(i)),
+                                },
+                                Some((_, c)) => value.push(c),
+                                None => return Err(TokenizeError::UnmatchedDoubleQuote(i)),
+                            }
+                        }
+                    }
+                    '\\' => {
+                        chars.next();
+                        match chars.next() {
+                            Some((_, '\n')) => {}
+                            Some((_, c)) => value.push(c),
+                            None => return Err(TokenizeError::TrailingBackslash),
+                        }
+                    }
+                    c if c.is_whitespace() || "|&;<>".contains(c) => break,
+                    c => {
+                        chars.next();
+                        value.push(c);
+                    }
+                }
+                end = chars.peek().map_or(command.len(), |&(i, _)| i);
+            }
+
+            // A file descriptor number directly before a redirection, like
+            // in `2>`, is part of it.
+            let is_fd =
+                !value.is_empty() && command[start..end].bytes().all(|b| b.is_ascii_digit());
+            if is_fd && matches!(chars.peek(), Some((_, '>' | '<'))) {
+                let end = redirect_end(&mut chars);
+                tokens.push(token(command, TokenKind::Redirect, start..end));
+                continue;
+            }
+
+            tokens.push(Token {
+                kind: TokenKind::Word,
+                value,
+                span: start..end,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Tidies up the whitespace in a command, leaving quoted strings alone.
+///
+/// Runs of whitespace become single spaces, with one around operators like
+/// `|` and `&&` and none before `;`. Tokens that touch, like in `2>&1` or
+/// `>file`, are kept together.
+pub fn tidy(command: &str) -> Result<String, TokenizeError> {
+    let tokens = tokenize(command)?;
+    let mut tidy = String::new();
+    let mut end = 0;
+    let mut previous: Option<&Token> = None;
+
+    for token in &tokens {
+        // Comments are the only thing the tokens leave out.
+        let gap = &command[end..token.span.start];
+        let comment = gap.trim();
+        if !comment.is_empty() {
+            if !tidy.is_empty() {
+                tidy.push(' ');
+            }
+            tidy += comment;
+        }
+
+        let is_newline = |t: &Token| t.value == "\n";
+        let space = match previous {
+            None => false,
+            Some(_) if !comment.is_empty() || is_newline(token) => false,
+            Some(previous) if is_newline(previous) => false,
+            Some(_) if token.kind == TokenKind::Separator => !token.value.starts_with(';'),
+            Some(previous) if previous.kind == TokenKind::Separator => true,
+            Some(_) => !gap.is_empty(),
+        };
+        if space {
+            tidy.push(' ');
+        }
+        tidy += &command[token.span.clone()];
+        end = token.span.end;
+        previous = Some(token);
+    }
+
+    let comment = command[end..].trim();
+    if !comment.is_empty() {
+        if !tidy.is_empty() {
+            tidy.push(' ');
+        }
+        tidy += comment;
+    }
+    Ok(tidy)
+}
+
+/// A common mistake in a command that makes the shell fail confusingly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mistake {
+    /// What is wrong and how to fix it.
+    pub message: String,
+    /// The command with the fix applied, if there is a safe one.
+    pub fixed: Option<String>,
+}
+
+/// Looks for unmatched quotes, trailing backslashes and redirections
+/// without a target.
+///
+/// Fixes are only offered if the fixed command splits into tokens, so that
+/// they don't swap one mistake for another.
+pub fn find_mistake(command: &str) -> Option<Mistake> {
+    let pipelines = match parse(command) {
+        Ok(pipelines) => pipelines,
+        Err(e) => {
+            let (message, fixed) = match e {
+                // A lone quote between letters is most likely an apostrophe,
+                // as in `echo don't`.
+                TokenizeError::UnmatchedSingleQuote(i)
+                    if command[..i].ends_with(char::is_alphanumeric)
+                        && command[i + 1..].starts_with(char::is_alphanumeric) =>
+                {
+                    (
+                        "unmatched single quote, escape it as \\' if it is an apostrophe",
+                        format!("{}\\{}", &command[..i], &command[i..]),
+                    )
+                }
+                TokenizeError::UnmatchedSingleQuote(_) => (
+                    "unmatched single quote, add a closing '",
+                    format!("{command}'"),
+                ),
+                TokenizeError::UnmatchedDoubleQuote(_) => (
+                    "unmatched double quote, add a closing \"",
+                    format!("{command}\""),
+                ),
+                TokenizeError::TrailingBackslash => (
+                    "trailing backslash with nothing to escape, remove it",
+                    command[..command.len() - 1].to_string(),
+                ),
+            };
+            return Some(Mistake {
+                message: message.to_string(),
+                fixed: tokenize(&fixed).is_ok().then_some(fixed),
+            });
+        }
+    };
+
+    let redirect = pipelines
+        .iter()
+        .flat_map(|p| &p.stages)
+        .flat_map(|s| &s.redirects)
+        .find(|r| r.target.is_none())?;
+    Some(Mistake {
+        message: format!("{} needs a file after it", redirect.operator),
+        fixed: None,
+    })
+}
+
+/// Consumes a redirection operator, returning where it ends.
+fn redirect_end(chars: &mut Peekable<CharIndices<'_>>) -> usize {
+    let (i, first) = chars.next().expect("called on an operator");
+    let mut end = i + 1;
+    // `>>`, `<<`, `>&`, `<&`, `<>` and `>|`.
+    if let Some(&(i, c)) = chars.peek() {
+        if c == first || c == '&' || (first == '<' && c == '>') || (first == '>' && c == '|') {
+            chars.next();
+            end = i + 1;
+        }
+    }
+    end
+}
+
+fn token(command: &str, kind: TokenKind, span: Range<usize>) -> Token {
+    Token {
+        kind,
+        value: command[span.clone()].to_string(),
+        span,
+    }
+}
+
+/// Commands connected by pipes, like `grep foo file | sort`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+    /// What comes after the pipeline, like `&&`, `;` or `&`, if anything.
+    pub separator: Option<String>,
+}
+
+/// A simple command in a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    /// Variables set for the command, like `LANG=C`.
+    pub assignments: Vec<String>,
+    /// The command and its arguments.
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+    /// Where the stage is in the command, in bytes.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// The operator, with the file descriptor if there is one, like `2>`.
+    pub operator: String,
+    /// What is redirected to or from, which is missing at the end of an
+    /// incomplete command.
+    pub target: Option<String>,
+}
+
+/// Parses a command into pipelines, without expanding anything.
+///
+/// Only the operators that [`tokenize`] knows about are understood, so
+/// things like subshells and `if` are just words.
+pub fn parse(command: &str) -> Result<Vec<Pipeline>, TokenizeError> {
+    let tokens = tokenize(command)?;
+    let mut pipelines = vec![];
+    let mut stages = vec![];
+    let mut stage: Option<Stage> = None;
+    let mut tokens = tokens.iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        match token.kind {
+            TokenKind::Separator => {
+                stages.extend(stage.take());
+                if token.value != "|" && token.value != "|&" {
+                    pipelines.push(Pipeline {
+                        stages: std::mem::take(&mut stages),
+                        separator: Some(token.value.clone()),
+                    });
+                }
+            }
+            TokenKind::Redirect | TokenKind::Word => {
+                let stage = stage.get_or_insert_with(|| Stage {
+                    assignments: vec![],
+                    words: vec![],
+                    redirects: vec![],
+                    span: token.span.clone(),
+                });
+                stage.span.end = token.span.end;
+                if token.kind == TokenKind::Redirect {
+                    let target = tokens.next_if(|t| t.kind == TokenKind::Word);
+                    if let Some(target) = target {
+                        stage.span.end = target.span.end;
+                    }
+                    stage.redirects.push(Redirect {
+                        operator: token.value.clone(),
+                        target: target.map(|t| t.value.clone()),
+                    });
+                } else if stage.words.is_empty() && is_assignment(&token.value) {
+                    stage.assignments.push(token.value.clone());
+                } else {
+                    stage.words.push(token.value.clone());
+                }
+            }
+        }
+    }
+
+    stages.extend(stage);
+    if !stages.is_empty() {
+        pipelines.push(Pipeline {
+            stages,
+            separator: None,
+        });
+    }
+    Ok(pipelines)
+}
+
+/// Splits tokens into the simple commands separated by pipes, `;`, `&&` and
+/// the like.
+pub fn commands(tokens: &[Token]) -> impl Iterator<Item = &[Token]> {
+    tokens
+        .split(|t| t.kind == TokenKind::Separator)
+        .filter(|c| !c.is_empty())
+}
+
+/// The simple command that the byte `offset` is in or right after.
+pub fn command_at(tokens: &[Token], offset: usize) -> Option<&[Token]> {
+    commands(tokens)
+        .take_while(|command| command[0].span.start <= offset)
+        .last()
+}
+
+/// The words making up a simple command, skipping leading variable
+/// assignments and redirections along with their targets.
+pub fn words(command: &[Token]) -> impl Iterator<Item = &str> {
+    word_tokens(command).map(|t| t.value.as_str())
+}
+
+/// The tokens of the words that [`words`] returns.
+pub fn word_tokens(command: &[Token]) -> impl Iterator<Item = &Token> {
+    let mut after_redirect = false;
+    command
+        .iter()
+        .filter(move |t| {
+            let is_target = after_redirect;
+            after_redirect = t.kind == TokenKind::Redirect;
+            t.kind == TokenKind::Word && !is_target
+        })
+        .skip_while(|t| is_assignment(&t.value))
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pipelines() {
+        let pipelines = parse("LANG=C grep -i 'a b' file 2>/dev/null | sort && ls >").unwrap();
+        assert_eq!(pipelines.len(), 2);
+        assert_eq!(pipelines[0].separator.as_deref(), Some("&&"));
+
+        let grep = &pipelines[0].stages[0];
+        assert_eq!(grep.assignments, ["LANG=C"]);
+        assert_eq!(grep.words, ["grep", "-i", "a b", "file"]);
+        assert_eq!(
+            grep.redirects,
+            [Redirect {
+                operator: "2>".to_string(),
+                target: Some("/dev/null".to_string()),
+            }]
+        );
+        assert_eq!(pipelines[0].stages[1].words, ["sort"]);
+
+        let ls = &pipelines[1];
+        assert_eq!(ls.separator, None);
+        assert_eq!(ls.stages[0].redirects[0].target, None);
+    }
+}
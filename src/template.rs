@@ -0,0 +1,153 @@
+use anyhow::{bail, Result};
+use tui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::editor::Editor;
+
+/// A command with fixed text and named fields to fill in, like
+/// `grep {pattern} {file}`.
+///
+/// Fields with the same name share their content. Braces around anything
+/// that isn't a plain name, as in `awk '{print $1}'` or `find -exec {}`, are
+/// kept as they are.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+    fields: Vec<(String, Editor)>,
+    active: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    /// An index into the fields.
+    Field(usize),
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut fields: Vec<(String, Editor)> = vec![];
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let name = rest[start + 1..]
+                .find('}')
+                .map(|end| &rest[start + 1..start + 1 + end])
+                .filter(|name| is_field_name(name));
+            let Some(name) = name else {
+                push_text(&mut parts, &rest[..=start]);
+                rest = &rest[start + 1..];
+                continue;
+            };
+
+            push_text(&mut parts, &rest[..start]);
+            let index = fields
+                .iter()
+                .position(|(n, _)| n == name)
+                .unwrap_or_else(|| {
+                    fields.push((name.to_string(), Editor::default()));
+                    fields.len() - 1
+                });
+            parts.push(Part::Field(index));
+            rest = &rest[start + name.len() + 2..];
+        }
+        push_text(&mut parts, rest);
+
+        if fields.is_empty() {
+            bail!("template has no fields, add some like {{name}}");
+        }
+        Ok(Self {
+            parts,
+            fields,
+            active: 0,
+        })
+    }
+
+    /// The command with all fields filled in.
+    pub fn command(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Field(i) => self.fields[*i].1.text(),
+            })
+            .collect()
+    }
+
+    /// The editor for the field being edited.
+    pub fn active(&mut self) -> &mut Editor {
+        &mut self.fields[self.active].1
+    }
+
+    pub fn next_field(&mut self) {
+        self.active = (self.active + 1) % self.fields.len();
+    }
+
+    pub fn previous_field(&mut self) {
+        self.active = (self.active + self.fields.len() - 1) % self.fields.len();
+    }
+
+    /// The template as styled text, and the column of the cursor in it.
+    ///
+    /// Fixed text is dimmed and fields are underlined, with the active one in
+    /// bold. Empty fields show their name, and the others can be masked.
+    pub fn render(&self, masked: bool) -> (Vec<Span<'static>>, u16) {
+        let mut spans = vec![];
+        let mut cursor = None;
+        let mut column = 0;
+
+        for part in &self.parts {
+            let span = match part {
+                Part::Text(text) => {
+                    Span::styled(text.clone(), Style::default().fg(Color::DarkGray))
+                }
+                Part::Field(i) => {
+                    let (name, editor) = &self.fields[*i];
+                    let mut style = Style::default().add_modifier(Modifier::UNDERLINED);
+                    if *i == self.active {
+                        style = style.add_modifier(Modifier::BOLD);
+                        let cursor_column = if masked {
+                            editor.masked_cursor_column()
+                        } else {
+                            editor.cursor_column()
+                        };
+                        cursor.get_or_insert(column + cursor_column);
+                    }
+                    if editor.text().is_empty() {
+                        Span::styled(name.clone(), style.fg(Color::DarkGray))
+                    } else if masked {
+                        Span::styled(editor.masked(), style)
+                    } else {
+                        Span::styled(editor.display(), style)
+                    }
+                }
+            };
+            column += span.content.width() as u16;
+            spans.push(span);
+        }
+
+        (spans, cursor.unwrap_or(column))
+    }
+}
+
+fn is_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn push_text(parts: &mut Vec<Part>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(Part::Text(last)) = parts.last_mut() {
+        last.push_str(text);
+    } else {
+        parts.push(Part::Text(text.to_string()));
+    }
+}
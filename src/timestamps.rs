@@ -0,0 +1,220 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+
+/// How to show timestamps found in the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// How long ago they were, like `2m ago`.
+    #[default]
+    Relative,
+    /// In the local time zone, like `2023-01-31 14:05:09`.
+    Local,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "relative" => Ok(Self::Relative),
+            "local" => Ok(Self::Local),
+            _ => bail!("expected one of relative, local"),
+        }
+    }
+}
+
+/// Rewrites the timestamps in `text` into `format`.
+///
+/// Recognised are ISO 8601 timestamps like `2023-01-31T13:05:09.123Z`, with
+/// or without a time zone, and Unix timestamps in seconds or milliseconds,
+/// which are numbers of 10 or 13 digits. Timestamps without a time zone are
+/// taken to be in local time.
+pub fn rewrite(text: &str, format: TimestampFormat, now: SystemTime) -> String {
+    let bytes = text.as_bytes();
+    let mut rewritten = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let at_word_start = i == 0 || !bytes[i - 1].is_ascii_alphanumeric();
+        if !bytes[i].is_ascii_digit() || !at_word_start {
+            i += 1;
+            continue;
+        }
+        let found = iso(&bytes[i..]).or_else(|| epoch(&bytes[i..]));
+        match found {
+            Some((time, len)) => {
+                rewritten += &text[copied..i];
+                rewritten += &show(time, format, now);
+                i += len;
+                copied = i;
+            }
+            // Skip the rest of the number.
+            None => {
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+        }
+    }
+    rewritten += &text[copied..];
+    rewritten
+}
+
+/// Parses a Unix timestamp at the start of `bytes`, returning it and its
+/// length.
+fn epoch(bytes: &[u8]) -> Option<(SystemTime, usize)> {
+    let len = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    if bytes.get(len).is_some_and(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+    let number: u64 = std::str::from_utf8(&bytes[..len]).ok()?.parse().ok()?;
+    let since_epoch = match len {
+        10 => Duration::from_secs(number),
+        13 => Duration::from_millis(number),
+        _ => return None,
+    };
+    Some((UNIX_EPOCH + since_epoch, len))
+}
+
+/// Parses an ISO 8601 timestamp at the start of `bytes`, like
+/// `2023-01-31T13:05:09`, with optional fractional seconds and time zone.
+/// Returns it and its length.
+fn iso(bytes: &[u8]) -> Option<(SystemTime, usize)> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = bytes.get(range)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|&(i, c)| bytes.get(i) != Some(&c))
+        || !matches!(bytes.get(10), Some(b'T' | b' '))
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let mut len = 19;
+    let mut nanos = 0;
+    if bytes.get(len) == Some(&b'.') {
+        let digits = bytes[len + 1..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        // Pad or cut to nanoseconds.
+        let fraction: String = std::str::from_utf8(&bytes[len + 1..len + 1 + digits])
+            .ok()?
+            .chars()
+            .chain(std::iter::repeat('0'))
+            .take(9)
+            .collect();
+        nanos = fraction.parse().ok()?;
+        len += 1 + digits;
+    }
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    let offset = match bytes.get(len) {
+        Some(b'Z') => {
+            len += 1;
+            0
+        }
+        Some(&sign @ (b'+' | b'-')) => {
+            let hours = number(len + 1..len + 3)?;
+            // Both `+01:00` and `+0100`.
+            let colon = usize::from(bytes.get(len + 3) == Some(&b':'));
+            let minutes = number(len + 3 + colon..len + 5 + colon)?;
+            len += 5 + colon;
+            let offset = hours * 3600 + minutes * 60;
+            if sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => local_offset(seconds)?,
+    };
+    if bytes.get(len).is_some_and(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+
+    let since_epoch = u64::try_from(seconds - offset).ok()?;
+    Some((UNIX_EPOCH + Duration::new(since_epoch, nanos), len))
+}
+
+/// Days since the Unix epoch of a date in the proleptic Gregorian calendar,
+/// from Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The offset of local time from UTC in seconds at a local time given in
+/// seconds since the epoch as if it were UTC.
+fn local_offset(local_seconds: i64) -> Option<i64> {
+    // Close enough to find the offset, apart from right at DST changes.
+    let tm = local_time(local_seconds)?;
+    Some(tm.tm_gmtoff)
+}
+
+/// The local time at some seconds since the epoch.
+pub fn local_time(seconds: i64) -> Option<libc::tm> {
+    let time = libc::time_t::try_from(seconds).ok()?;
+    // SAFETY: localtime_r only writes to the tm it is given.
+    unsafe {
+        let mut tm = std::mem::zeroed();
+        (!libc::localtime_r(&time, &mut tm).is_null()).then_some(tm)
+    }
+}
+
+fn show(time: SystemTime, format: TimestampFormat, now: SystemTime) -> String {
+    match format {
+        TimestampFormat::Relative => match now.duration_since(time) {
+            Ok(ago) => format!("{} ago", short_duration(ago)),
+            Err(e) => format!("in {}", short_duration(e.duration())),
+        },
+        TimestampFormat::Local => {
+            let seconds = match time.duration_since(UNIX_EPOCH) {
+                Ok(since) => since.as_secs() as i64,
+                Err(e) => -(e.duration().as_secs() as i64),
+            };
+            match local_time(seconds) {
+                Some(tm) => format!(
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    tm.tm_year + 1900,
+                    tm.tm_mon + 1,
+                    tm.tm_mday,
+                    tm.tm_hour,
+                    tm.tm_min,
+                    tm.tm_sec
+                ),
+                None => show(time, TimestampFormat::Relative, now),
+            }
+        }
+    }
+}
+
+/// A duration in its largest whole unit, like `2m` or `3d`.
+fn short_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}